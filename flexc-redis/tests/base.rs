@@ -1,7 +1,7 @@
 use flexc_redis::{flexc::Builder, Pool, RedisConnectionManager};
 use std::sync::{atomic::*, Arc};
 pub use std::time::*;
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Counter(Arc<AtomicUsize>);
 
 impl Counter {
@@ -43,10 +43,10 @@ pub fn block_on(fut: impl std::future::Future<Output = ()>) {
     rt.block_on(fut)
 }
 
-#[cfg(any(feature = "async-rt", feature = "async-rt-tls"))]
+#[cfg(any(feature = "async-rt", feature = "async-rt-tls", feature = "smol-rt"))]
 pub use async_std::task::{block_on as block_on_, sleep, spawn};
 
-#[cfg(any(feature = "async-rt", feature = "async-rt-tls"))]
+#[cfg(any(feature = "async-rt", feature = "async-rt-tls", feature = "smol-rt"))]
 pub fn block_on(fut: impl std::future::Future<Output = ()>) {
     block_on_(async { assert!(std::env::args().count() >= 1) });
     block_on_(fut)
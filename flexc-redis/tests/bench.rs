@@ -65,7 +65,7 @@ fn redis_pool_bench() {
         }
 
         counter.counter();
-        sleep(TIME.clone() + Duration::from_secs(1)).await;
+        sleep(TIME + Duration::from_secs(1)).await;
         let count = counter.count() - 1;
 
         println!(
@@ -72,7 +72,7 @@ fn redis_pool_lantency() {
         }
 
         let num = 10_000 + 1;
-        let mut mss = (0..num).into_iter().map(|_| 0).collect::<Vec<u64>>();
+        let mut mss = (0..num).map(|_| 0).collect::<Vec<u64>>();
         counter.counter();
 
         std::mem::drop(mp);
@@ -90,8 +90,7 @@ fn redis_pool_lantency() {
         for (i, c) in mss
             .iter()
             .enumerate()
-            .map(|(i, c)| if *c == 0 { None } else { Some((i, c)) })
-            .filter_map(|ic| ic)
+            .filter_map(|(i, c)| if *c == 0 { None } else { Some((i, c)) })
         {
             println!("{}: {:.2}, {}", i, *c as f64 / count as f64, c);
         }
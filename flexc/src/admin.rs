@@ -0,0 +1,89 @@
+//! A JSON admin handler usable from any HTTP framework.
+//!
+//! - `GET /pools` => the state, health and recent outcomes of every pool by name
+//! - `GET /pools/{name}` => the same with the slots and config of a pool
+//! - `POST /pools/{name}/clear_idle`, `POST /pools/{name}/pause`, `POST /pools/{name}/resume`
+use serde_json::{json, Map, Value};
+
+use crate::{Builder, PoolControl};
+
+/// Serves an admin request for the named pools, returns the status code and the JSON body.
+pub fn handle(pools: &[(&str, &dyn PoolControl)], method: &str, path: &str) -> (u16, String) {
+    let (code, body) = route(pools, method, path);
+    (code, body.to_string())
+}
+
+fn route(pools: &[(&str, &dyn PoolControl)], method: &str, path: &str) -> (u16, Value) {
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+
+    let find = |name: &str| pools.iter().find(|(n, _)| *n == name).map(|(_, p)| *p);
+    match (method, segments.as_slice()) {
+        ("GET", ["pools"]) => {
+            let all = pools
+                .iter()
+                .map(|(name, pool)| (name.to_string(), summary(*pool)))
+                .collect::<Map<_, _>>();
+            (200, Value::Object(all))
+        }
+        ("GET", ["pools", name]) => match find(name) {
+            Some(pool) => {
+                let mut detail = summary(pool);
+                detail["slots"] = json!(pool.slots());
+                detail["config"] = config(pool.config());
+                (200, detail)
+            }
+            None => not_found(name),
+        },
+        ("POST", ["pools", name, action]) => {
+            let pool = match find(name) {
+                Some(pool) => pool,
+                None => return not_found(name),
+            };
+            match *action {
+                "clear_idle" => pool.clear_idle(),
+                "pause" => pool.pause(),
+                "resume" => pool.resume(),
+                _ => {
+                    return (
+                        404,
+                        json!({ "error": format!("unknown action: {}", action) }),
+                    )
+                }
+            }
+            (200, summary(pool))
+        }
+        (_, ["pools", ..]) => (405, json!({ "error": "method not allowed" })),
+        _ => (404, json!({ "error": "not found" })),
+    }
+}
+
+fn not_found(name: &str) -> (u16, Value) {
+    (404, json!({ "error": format!("unknown pool: {}", name) }))
+}
+
+fn summary(pool: &dyn PoolControl) -> Value {
+    json!({
+        "state": pool.state(),
+        "health": pool.health(),
+        "recent": pool.recent(),
+        "paused": pool.is_paused(),
+        "closed": pool.is_closed(),
+    })
+}
+
+fn config(cfg: &Builder) -> Value {
+    json!({
+        "maxsize": cfg.maxsize,
+        "check": cfg.check,
+        "timeout": cfg.timeout,
+        "recycle_policy": cfg.recycle.is_some(),
+        "leak_detection": cfg.leak_detection,
+        "max_hold_time": cfg.max_hold_time,
+        "discard_on_panic": cfg.discard_on_panic,
+        "shards": cfg.shards,
+    })
+}
@@ -6,16 +6,16 @@ pub(crate) trait SemaphoreWrap {
     fn wrapped_new(permits: usize) -> Arc<Self>;
     fn wrapped_try_acquire_owned(self: &Arc<Self>) -> Result<Option<OwnedSemaphorePermit>, Closed>;
     async fn wrapped_acquire_owned(self: &Arc<Self>) -> Result<OwnedSemaphorePermit, Closed>;
-    fn close(&self) {}
+    fn close(&self);
+    fn is_closed(&self) -> bool;
 }
 
-#[cfg(any(feature = "tokio-rt"))]
-pub(crate) use tokio::{
-    sync::{OwnedSemaphorePermit, Semaphore},
-    time::timeout,
-};
+// the semaphores are runtime independent, prefer tokio's one if it's enabled
 
-#[cfg(any(feature = "tokio-rt"))]
+#[cfg(feature = "tokio-rt")]
+pub(crate) use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[cfg(feature = "tokio-rt")]
 #[crate::async_trait]
 impl SemaphoreWrap for Semaphore {
     fn wrapped_new(permits: usize) -> Arc<Self> {
@@ -36,23 +36,61 @@ impl SemaphoreWrap for Semaphore {
     fn close(&self) {
         self.close();
     }
+    fn is_closed(&self) -> bool {
+        self.is_closed()
+    }
 }
 
-#[cfg(any(feature = "async-rt"))]
-pub(crate) use async_lock::{Semaphore, SemaphoreGuardArc as OwnedSemaphorePermit};
-#[cfg(any(feature = "async-rt"))]
-pub(crate) use async_std::future::timeout;
+#[cfg(not(feature = "tokio-rt"))]
+pub(crate) use async_lock::SemaphoreGuardArc as OwnedSemaphorePermit;
+
+// async_lock::Semaphore can't be closed, wake up the waiters by the event
+#[cfg(not(feature = "tokio-rt"))]
+pub(crate) struct Semaphore {
+    inner: Arc<async_lock::Semaphore>,
+    closed: std::sync::atomic::AtomicBool,
+    event: event_listener::Event,
+}
 
-#[cfg(any(feature = "async-rt"))]
+#[cfg(not(feature = "tokio-rt"))]
 #[crate::async_trait]
 impl SemaphoreWrap for Semaphore {
     fn wrapped_new(permits: usize) -> Arc<Self> {
-        Arc::new(Semaphore::new(permits))
+        Arc::new(Semaphore {
+            inner: Arc::new(async_lock::Semaphore::new(permits)),
+            closed: Default::default(),
+            event: event_listener::Event::new(),
+        })
     }
     fn wrapped_try_acquire_owned(self: &Arc<Self>) -> Result<Option<OwnedSemaphorePermit>, Closed> {
-        Ok(self.try_acquire_arc())
+        if self.is_closed() {
+            return Err(Closed);
+        }
+        Ok(self.inner.try_acquire_arc())
     }
     async fn wrapped_acquire_owned(self: &Arc<Self>) -> Result<OwnedSemaphorePermit, Closed> {
-        Ok(self.acquire_arc().await)
+        use futures_util::future::{select, Either};
+
+        let closed = self.event.listen();
+        if self.is_closed() {
+            return Err(Closed);
+        }
+        let acquire = self.inner.acquire_arc();
+        futures_util::pin_mut!(acquire);
+        match select(acquire, closed).await {
+            Either::Left((p, _)) => Ok(p),
+            Either::Right(_) => Err(Closed),
+        }
+    }
+    fn close(&self) {
+        use std::sync::atomic::Ordering;
+
+        self.closed.store(true, Ordering::SeqCst);
+        self.event.notify(usize::MAX);
+    }
+    fn is_closed(&self) -> bool {
+        use std::sync::atomic::Ordering;
+
+        self.closed.load(Ordering::SeqCst)
     }
 }
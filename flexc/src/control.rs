@@ -0,0 +1,134 @@
+use std::sync::atomic::Ordering;
+
+use crate::compat::SemaphoreWrap;
+use crate::{Builder, Health, Manager, Pool, Recent, SharedPool, Slot, SlotStatus, State};
+
+/// The operations of a pool regardless of its `Manager`, for admin handlers and registries.
+pub trait PoolControl: Send + Sync {
+    fn state(&self) -> State;
+    fn health(&self) -> Health;
+    fn recent(&self) -> Recent;
+    fn slots(&self) -> Vec<Slot>;
+    fn config(&self) -> &Builder;
+    fn clear_idle(&self);
+    fn pause(&self);
+    fn resume(&self);
+    fn is_paused(&self) -> bool;
+    fn close(&self);
+    fn is_closed(&self) -> bool;
+}
+
+impl<M: Manager> PoolControl for SharedPool<M> {
+    fn state(&self) -> State {
+        self.status.state()
+    }
+    fn health(&self) -> Health {
+        let now = self.clock.elapsed();
+        self.outcomes.health(now, &self.state(), self.is_closed())
+    }
+    fn recent(&self) -> Recent {
+        self.outcomes.recent(self.clock.elapsed())
+    }
+    fn slots(&self) -> Vec<Slot> {
+        let now = self.clock.elapsed();
+        self.history
+            .iter()
+            .enumerate()
+            .map(|(idx, history)| {
+                let status = SlotStatus::from_u8(self.status.get(idx));
+                history.slot(idx, status, now)
+            })
+            .collect()
+    }
+    fn config(&self) -> &Builder {
+        &self.cfg
+    }
+    fn clear_idle(&self) {
+        // taken with permits like checkouts so the permit holders never miss a connection,
+        // and pushed back after all taken so a pop and push of the same shard never cycle the same ones
+        let mut cleared = vec![];
+        while let Ok(Some(permit)) = self.semaphore.wrapped_try_acquire_owned() {
+            match self.queue.pop() {
+                Some(mut conn) => {
+                    conn.discard();
+                    cleared.push((conn, permit));
+                }
+                None => break,
+            }
+        }
+        for (conn, permit) in cleared {
+            self.queue.push(conn).ok();
+            drop(permit);
+        }
+    }
+    fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+    fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.resumed.notify(usize::MAX);
+    }
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+    fn close(&self) {
+        SemaphoreWrap::close(&*self.semaphore);
+        self.drain_idle();
+        self.resumed.notify(usize::MAX);
+        self.status.notify();
+    }
+    fn is_closed(&self) -> bool {
+        SemaphoreWrap::is_closed(&*self.semaphore)
+    }
+}
+
+impl<M: Manager> SharedPool<M> {
+    // drops the idle connections without permits once closed, the permit holders miss them meanwhile
+    // and retry
+    fn drain_idle(&self) {
+        let mut drained = vec![];
+        while let Some(mut conn) = self.queue.pop() {
+            conn.discard();
+            drained.push(conn);
+        }
+        for conn in drained {
+            self.queue.push(conn).ok();
+        }
+    }
+}
+
+impl<M: Manager> PoolControl for Pool<M> {
+    fn state(&self) -> State {
+        Pool::state(self)
+    }
+    fn health(&self) -> Health {
+        Pool::health(self)
+    }
+    fn recent(&self) -> Recent {
+        Pool::recent(self)
+    }
+    fn slots(&self) -> Vec<Slot> {
+        Pool::slots(self)
+    }
+    fn config(&self) -> &Builder {
+        Pool::config(self)
+    }
+    fn clear_idle(&self) {
+        Pool::clear_idle(self)
+    }
+    fn pause(&self) {
+        Pool::pause(self)
+    }
+    fn resume(&self) {
+        Pool::resume(self)
+    }
+    fn is_paused(&self) -> bool {
+        Pool::is_paused(self)
+    }
+    fn close(&self) {
+        Pool::close(self)
+    }
+    fn is_closed(&self) -> bool {
+        Pool::is_closed(self)
+    }
+}
@@ -1,41 +1,67 @@
 use std::{
     error,
     fmt::{self, Debug, Display},
+    time::Duration,
 };
 /// The error type returned by methods in this crate.
 pub enum Error<E> {
     /// Manager Errors
     Inner(E),
+    /// `Manager::connect` failed for the slot `idx` after `elapsed`
+    Connect {
+        error: E,
+        idx: usize,
+        elapsed: Duration,
+    },
+    /// `Manager::check` failed for the slot `idx` after `elapsed`, the connection was dropped
+    Check {
+        error: E,
+        idx: usize,
+        elapsed: Duration,
+    },
     /// Timeout
     Timeout(&'static str),
+    /// Cancelled before getting a connection, see `Pool::get_with_cancel`
+    Cancelled,
+    /// No runtime to drive the blocking checkout, see `Pool::get_blocking`
+    Runtime(&'static str),
     /// Pool already closed
     Closed,
 }
 
 impl<E> Error<E> {
+    /// the error of `Manager` for `Inner`, `Connect` and `Check`
     pub fn into_inner(self) -> Option<E> {
         match self {
             Error::Inner(e) => Some(e),
+            Error::Connect { error, .. } | Error::Check { error, .. } => Some(error),
             _ => None,
         }
     }
+    /// `Inner`, `Connect` or `Check`
     pub fn is_inner(&self) -> bool {
-        match *self {
-            Error::Inner(_) => true,
-            _ => false,
-        }
+        matches!(
+            *self,
+            Error::Inner(_) | Error::Connect { .. } | Error::Check { .. }
+        )
+    }
+    pub fn is_connect(&self) -> bool {
+        matches!(*self, Error::Connect { .. })
+    }
+    pub fn is_check(&self) -> bool {
+        matches!(*self, Error::Check { .. })
     }
     pub fn is_timeout(&self) -> bool {
-        match *self {
-            Error::Timeout(_) => true,
-            _ => false,
-        }
+        matches!(*self, Error::Timeout(_))
+    }
+    pub fn is_cancelled(&self) -> bool {
+        matches!(*self, Error::Cancelled)
+    }
+    pub fn is_runtime(&self) -> bool {
+        matches!(*self, Error::Runtime(_))
     }
     pub fn is_closed(&self) -> bool {
-        match *self {
-            Error::Closed => true,
-            _ => false,
-        }
+        matches!(*self, Error::Closed)
     }
 }
 
@@ -49,7 +75,27 @@ impl<E: Display> Display for Error<E> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Inner(ref err) => write!(f, "{}", err),
+            Error::Connect {
+                ref error,
+                idx,
+                elapsed,
+            } => write!(
+                f,
+                "Connect conn-{} failed after {:?}: {}",
+                idx, elapsed, error
+            ),
+            Error::Check {
+                ref error,
+                idx,
+                elapsed,
+            } => write!(
+                f,
+                "Check conn-{} failed after {:?}: {}",
+                idx, elapsed, error
+            ),
             Error::Timeout(loc) => write!(f, "Timed out in flexc.{}", loc),
+            Error::Cancelled => write!(f, "Cancelled in flexc"),
+            Error::Runtime(reason) => write!(f, "Runtime unavailable in flexc: {}", reason),
             Error::Closed => write!(f, "Pool Closed in flexc"),
         }
     }
@@ -59,7 +105,27 @@ impl<E: Debug> Debug for Error<E> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Inner(ref err) => write!(f, "{:?}", err),
+            Error::Connect {
+                ref error,
+                idx,
+                elapsed,
+            } => write!(
+                f,
+                "Connect conn-{} failed after {:?}: {:?}",
+                idx, elapsed, error
+            ),
+            Error::Check {
+                ref error,
+                idx,
+                elapsed,
+            } => write!(
+                f,
+                "Check conn-{} failed after {:?}: {:?}",
+                idx, elapsed, error
+            ),
             Error::Timeout(loc) => write!(f, "Timed out in flexc.{}", loc),
+            Error::Cancelled => write!(f, "Cancelled in flexc"),
+            Error::Runtime(reason) => write!(f, "Runtime unavailable in flexc: {}", reason),
             Error::Closed => write!(f, "Pool Closed in flexc"),
         }
     }
@@ -72,6 +138,7 @@ where
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
             Error::Inner(ref err) => Some(err),
+            Error::Connect { ref error, .. } | Error::Check { ref error, .. } => Some(error),
             _ => None,
         }
     }
@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::State;
+
+// the outcomes are counted in buckets of 10 seconds, the last 6 buckets make the window,
+// so the outcomes older than 50 to 60 seconds are forgotten
+const BUCKET_SECS: u64 = 10;
+const BUCKETS: usize = 6;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The health of a `Pool` judged by the outcomes of about the last minute, see `Pool::health`.
+pub enum Health {
+    Healthy,
+    /// Some connects or checks failed, checkouts timed out or all connections in use with waiters
+    Degraded,
+    /// The pool closed or every connect and check failed with no connection left
+    Unhealthy,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The outcomes of about the last minute judging the `Health`, see `Pool::recent`.
+pub struct Recent {
+    /// The number of connections established
+    pub connected: u32,
+    /// The number of checks passed
+    pub checked: u32,
+    /// The number of connects and checks failed
+    pub failed: u32,
+    /// The number of checkouts timed out
+    pub timeouts: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Connected,
+    Checked,
+    Failed,
+    Timeout,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    // the index of the 10 seconds counted plus 1, 0 => never used
+    epoch: AtomicU64,
+    // by `Outcome as usize`
+    counts: [AtomicU32; 4],
+}
+
+// the recent outcomes of connect, check and checkout, counted without locking
+#[derive(Debug, Default)]
+pub(crate) struct Outcomes([Bucket; BUCKETS]);
+
+fn epoch(now: Duration) -> u64 {
+    now.as_secs() / BUCKET_SECS + 1
+}
+
+impl Outcomes {
+    pub(crate) fn record(&self, now: Duration, outcome: Outcome) {
+        let epoch = epoch(now);
+        let bucket = &self.0[epoch as usize % BUCKETS];
+        let seen = bucket.epoch.load(Ordering::Acquire);
+        if seen < epoch
+            && bucket
+                .epoch
+                .compare_exchange(seen, epoch, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            // the stale counts are cleared by the first outcome of the epoch,
+            // a racing outcome may be lost with them
+            for count in &bucket.counts {
+                count.store(0, Ordering::Relaxed);
+            }
+        }
+        bucket.counts[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn recent(&self, now: Duration) -> Recent {
+        let counts = self.counts(now);
+        Recent {
+            connected: counts[Outcome::Connected as usize],
+            checked: counts[Outcome::Checked as usize],
+            failed: counts[Outcome::Failed as usize],
+            timeouts: counts[Outcome::Timeout as usize],
+        }
+    }
+
+    // the counts of the buckets in the window by `Outcome as usize`
+    fn counts(&self, now: Duration) -> [u32; 4] {
+        let epoch = epoch(now);
+        let mut counts = [0; 4];
+        for bucket in &self.0 {
+            if bucket.epoch.load(Ordering::Acquire) + BUCKETS as u64 > epoch {
+                for (sum, count) in counts.iter_mut().zip(&bucket.counts) {
+                    *sum += count.load(Ordering::Relaxed);
+                }
+            }
+        }
+        counts
+    }
+
+    pub(crate) fn health(&self, now: Duration, state: &State, closed: bool) -> Health {
+        if closed {
+            return Health::Unhealthy;
+        }
+
+        let recent = self.recent(now);
+        let failures = recent.failed;
+        let attempts = recent.connected + recent.checked + failures;
+
+        if failures > 0 && failures == attempts && state.size == 0 {
+            Health::Unhealthy
+        } else if failures > 0
+            || recent.timeouts > 0
+            || (state.idle + state.empty == 0 && state.wait > 0)
+        {
+            Health::Degraded
+        } else {
+            Health::Healthy
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::backtrace::Backtrace;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::compat::OwnedSemaphorePermit;
+use crate::status::{Slot, SlotStatus};
+
+/// A connection currently checked out of the pool.
+#[derive(Clone, Debug)]
+pub struct HeldConnection {
+    /// The index of the connection in the pool
+    pub idx: usize,
+    /// Where the connection was checked out
+    pub caller: &'static Location<'static>,
+    /// How long the connection has been held
+    pub held: Duration,
+    /// Captured at checkout if `Builder::leak_backtrace` enabled
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+/// Called for each connection held longer than `Builder::leak_detection`.
+pub trait LeakObserver: Send + Sync + 'static {
+    fn leaked(&self, conn: &HeldConnection);
+}
+
+impl<F> LeakObserver for F
+where
+    F: Fn(&HeldConnection) + Send + Sync + 'static,
+{
+    fn leaked(&self, conn: &HeldConnection) {
+        self(conn)
+    }
+}
+
+// the checkout record of a slot, holds the permit while the connection in use,
+// only kept if leak_detection or max_hold_time enabled
+#[derive(Default)]
+pub(crate) struct Lease {
+    caller: Option<&'static Location<'static>>,
+    since: Duration,
+    backtrace: Option<Arc<Backtrace>>,
+    reported: bool,
+    permit: Option<OwnedSemaphorePermit>,
+    // bumped when revoked, the stale connection is discarded on drop
+    pub(crate) generation: u64,
+}
+
+impl Lease {
+    pub(crate) fn hold(
+        &mut self,
+        caller: &'static Location<'static>,
+        now: Duration,
+        backtrace: bool,
+        permit: Option<OwnedSemaphorePermit>,
+    ) {
+        self.caller = Some(caller);
+        self.since = now;
+        self.backtrace = if backtrace {
+            Some(Arc::new(Backtrace::force_capture()))
+        } else {
+            None
+        };
+        self.reported = false;
+        self.permit = permit;
+    }
+
+    pub(crate) fn release(&mut self) -> Option<OwnedSemaphorePermit> {
+        self.caller = None;
+        self.backtrace = None;
+        self.permit.take()
+    }
+
+    // time left before the lease is revoked
+    pub(crate) fn expires(&self, now: Duration, max_hold: Duration) -> Option<Duration> {
+        self.caller
+            .map(|_| (self.since + max_hold).checked_sub(now).unwrap_or_default())
+    }
+
+    pub(crate) fn revoke(&mut self) -> Option<OwnedSemaphorePermit> {
+        self.generation += 1;
+        self.release()
+    }
+
+    pub(crate) fn held(&self, idx: usize, now: Duration) -> Option<HeldConnection> {
+        self.caller.map(|caller| HeldConnection {
+            idx,
+            caller,
+            held: now - self.since,
+            backtrace: self.backtrace.clone(),
+        })
+    }
+
+    // returns the holder only once for each lease
+    pub(crate) fn leaked(
+        &mut self,
+        idx: usize,
+        now: Duration,
+        threshold: Duration,
+    ) -> Option<HeldConnection> {
+        if self.reported || self.caller.is_none() || now - self.since < threshold {
+            return None;
+        }
+        self.reported = true;
+        self.held(idx, now)
+    }
+}
+
+// the history of a slot for `Pool::slots`, the times in nanos since the pool built, 0 => none
+#[derive(Default)]
+pub(crate) struct History {
+    connected: AtomicU64,
+    checked: AtomicU64,
+    checkout: AtomicU64,
+    uses: AtomicU64,
+}
+
+impl History {
+    pub(crate) fn connected(&self, now: Duration) {
+        self.connected.store(nanos(now), Ordering::Relaxed);
+        self.checked.store(0, Ordering::Relaxed);
+        self.checkout.store(0, Ordering::Relaxed);
+        self.uses.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn checked(&self, now: Duration) {
+        self.checked.store(nanos(now), Ordering::Relaxed);
+    }
+
+    pub(crate) fn checkout(&self, now: Duration, uses: u64) {
+        self.checkout.store(nanos(now), Ordering::Relaxed);
+        self.uses.store(uses, Ordering::Relaxed);
+    }
+
+    pub(crate) fn slot(&self, idx: usize, status: SlotStatus, now: Duration) -> Slot {
+        let connected = status != SlotStatus::Empty && status != SlotStatus::Connecting;
+        let since = |time: &AtomicU64| match time.load(Ordering::Relaxed) {
+            0 => None,
+            t => Some(now.saturating_sub(Duration::from_nanos(t))).filter(|_| connected),
+        };
+        let since_checkout = since(&self.checkout);
+        Slot {
+            idx,
+            status,
+            age: since(&self.connected),
+            since_check: since(&self.checked),
+            since_checkout,
+            held: since_checkout.filter(|_| status == SlotStatus::Inuse),
+            uses: if connected {
+                self.uses.load(Ordering::Relaxed)
+            } else {
+                0
+            },
+        }
+    }
+}
+
+// never 0 for a time
+fn nanos(time: Duration) -> u64 {
+    time.as_nanos() as u64 + 1
+}
+
+pub(crate) fn report(conn: &HeldConnection) {
+    match conn.backtrace.as_ref() {
+        Some(bt) => log::warn!(
+            "flexc: connection-{} held for {:?} by {}, backtrace:\n{}",
+            conn.idx,
+            conn.held,
+            conn.caller,
+            bt
+        ),
+        None => log::warn!(
+            "flexc: connection-{} held for {:?} by {}",
+            conn.idx,
+            conn.held,
+            conn.caller
+        ),
+    }
+}
@@ -1,18 +1,56 @@
-use crossbeam_queue::ArrayQueue;
+use crossbeam_utils::CachePadded;
+use event_listener::Event;
+use futures_util::Stream;
 use std::fmt;
-use std::sync::{Arc, Weak};
+use std::future::Future;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe, Location};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
 use std::time::{Duration, Instant};
 
-use compat::{timeout, OwnedSemaphorePermit, Semaphore, SemaphoreWrap};
+use compat::{OwnedSemaphorePermit, Semaphore, SemaphoreWrap};
+use runtime::{timeout, yield_now};
 
 pub use async_trait::async_trait;
+pub use control::PoolControl;
 pub use error::Error;
-pub use status::State;
+pub use health::{Health, Recent};
+use health::{Outcome, Outcomes};
+pub use lease::{HeldConnection, LeakObserver};
+use lease::{History, Lease};
+pub use local::{LocalManager, LocalPool, LocalPooledConnection};
+use metrics::Metrics;
+pub use recycle::{ConnInfo, RecyclePolicy};
+pub use registry::PoolRegistry;
+pub use resource::{PooledResource, ResourceManager, ResourcePool};
+#[cfg(feature = "async-rt")]
+pub use runtime::AsyncStdRuntime;
+#[cfg(feature = "smol-rt")]
+pub use runtime::SmolRuntime;
+#[cfg(feature = "tokio-rt")]
+pub use runtime::TokioRuntime;
+pub use runtime::{BoxFuture, Runtime};
+use shard::Shards;
 use status::Status;
+pub use status::{Slot, SlotStatus, State};
 
+#[cfg(feature = "admin")]
+pub mod admin;
 mod compat;
+mod control;
 mod error;
+mod health;
+mod lease;
+mod local;
+mod metrics;
+mod recycle;
+mod registry;
+mod resource;
+mod runtime;
+mod shard;
 mod status;
+mod watch;
 
 pub struct Pool<M: Manager> {
     shared: Arc<SharedPool<M>>,
@@ -24,7 +62,7 @@ impl<M: Manager> Pool<M> {
     }
 
     pub fn state(&self) -> State {
-        self.shared.status.state()
+        self.shared.state()
     }
 
     pub fn manager(&self) -> &M {
@@ -35,6 +73,124 @@ impl<M: Manager> Pool<M> {
         &self.shared.cfg
     }
 
+    /// close the pool, the waiters get `Error::Closed`, idle connections are dropped
+    /// and the connections in use are dropped when returned
+    pub fn close(&self) {
+        self.shared.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+
+    /// drop the idle connections, they are reconnected at next checkout
+    ///
+    /// the connections are taken with their permits like checkouts, a concurrent `try_get` may find
+    /// all in use meanwhile
+    pub fn clear_idle(&self) {
+        self.shared.clear_idle()
+    }
+
+    /// stop handing out connections, `get` waits until resumed or timeout and `try_get` gets `None`
+    pub fn pause(&self) {
+        self.shared.pause()
+    }
+
+    pub fn resume(&self) {
+        self.shared.resume()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.is_paused()
+    }
+
+    // wait until the pool resumed
+    async fn resumed(&self) -> Result<(), Error<M::Error>> {
+        loop {
+            if self.is_closed() {
+                return Err(Error::Closed);
+            }
+            if !self.is_paused() {
+                return Ok(());
+            }
+            let listener = self.shared.resumed.listen();
+            if self.is_paused() && !self.is_closed() {
+                listener.await;
+            }
+        }
+    }
+
+    /// a stream of the state, yields the current one and then whenever the counts change,
+    /// ends when the pool closed
+    ///
+    /// `coalesce` => wait the duration after a change to yield the changes in between once
+    pub fn watch(&self, coalesce: Option<Duration>) -> impl Stream<Item = State> + Send + 'static {
+        watch::Watch::new(
+            Arc::downgrade(&self.shared),
+            self.shared.status.clone(),
+            coalesce,
+        )
+        .into_stream()
+    }
+
+    /// a snapshot of each slot for debugging
+    pub fn slots(&self) -> Vec<Slot> {
+        self.shared.slots()
+    }
+
+    /// judged by the connects, checks and checkouts of about the last minute(counted in 10 second buckets)
+    /// and the capacity available
+    pub fn health(&self) -> Health {
+        self.shared.health()
+    }
+
+    /// the connects, checks and timeouts the health judged by
+    pub fn recent(&self) -> Recent {
+        self.shared.recent()
+    }
+
+    /// wait until at least `min_connected` connections established, at most `maxsize`
+    ///
+    /// connections are established by checkouts, `build` or `start_connections`
+    pub async fn wait_ready(
+        &self,
+        min_connected: usize,
+        duration: Option<Duration>,
+    ) -> Result<(), Error<M::Error>> {
+        let min_connected = min_connected.min(self.config().maxsize);
+        let ready = async {
+            loop {
+                let listener = self.shared.status.listen();
+                if self.is_closed() {
+                    return Err(Error::Closed);
+                }
+                if self.state().size as usize >= min_connected {
+                    return Ok(());
+                }
+                listener.await;
+            }
+        };
+
+        match duration {
+            Some(duration) => timeout(&*self.shared.runtime, duration, ready)
+                .await
+                .unwrap_or(Err(Error::Timeout("ready"))),
+            None => ready.await,
+        }
+    }
+
+    /// the connections currently checked out and who holds them,
+    /// empty unless `Builder::leak_detection` or `Builder::max_hold_time` set
+    pub fn held_connections(&self) -> Vec<HeldConnection> {
+        let now = self.shared.clock.elapsed();
+        self.shared
+            .leases
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, lease)| lease.lock().unwrap().held(idx, now))
+            .collect()
+    }
+
     /// start max-size connections
     pub async fn start_connections(&self) -> Result<usize, Error<M::Error>> {
         use futures_util::{stream::futures_unordered::FuturesUnordered, StreamExt};
@@ -55,123 +211,419 @@ impl<M: Manager> Pool<M> {
     }
 
     /// get without waiting idle connection, default timeout is for connect and check
-    pub async fn try_get(&self) -> Result<Option<PooledConnection<M>>, Error<M::Error>> {
-        self.try_get_timeout(self.config().timeout).await
+    #[track_caller]
+    pub fn try_get(
+        &self,
+    ) -> impl Future<Output = Result<Option<PooledConnection<M>>, Error<M::Error>>> + '_ {
+        self.try_get_timeout(self.config().timeout)
     }
 
     /// get without waiting idle connection, custom timeout is for connect and check
-    pub async fn try_get_timeout(
+    #[track_caller]
+    pub fn try_get_timeout(
         &self,
         duration: Option<Duration>,
-    ) -> Result<Option<PooledConnection<M>>, Error<M::Error>> {
-        let _wait = Arc::downgrade(&self.shared.status.0);
-        let mut error = "wait";
+    ) -> impl Future<Output = Result<Option<PooledConnection<M>>, Error<M::Error>>> + '_ {
+        self.try_get_at(duration, Location::caller())
+    }
 
-        let permit = match self.shared.semaphore.wrapped_try_acquire_owned() {
-            Ok(Some(p)) => p,
-            Ok(None) => return Ok(None),
-            Err(_) => return Err(Error::Closed),
+    async fn try_get_at(
+        &self,
+        duration: Option<Duration>,
+        caller: &'static Location<'static>,
+    ) -> Result<Option<PooledConnection<M>>, Error<M::Error>> {
+        let start = self.shared.metrics.start();
+        let mut conn = match self.checkout(caller, start)? {
+            Checkout::Ready(conn) => return Ok(Some(self.checked_out(conn))),
+            Checkout::Fill(conn) => conn,
+            Checkout::Busy => {
+                self.shared.sweep();
+                return Ok(None);
+            }
         };
 
-        let conn = self.shared.queue.pop();
-        if conn.is_none() {
-            return Ok(None);
-        }
+        let mut error = "wait";
 
         let fut = async move {
-            let mut conn = PooledConnection(conn);
             let con = conn.0.as_mut().expect("try get");
-            con.permit = Some(permit);
 
             match self.fill_conn(&mut error, con).await {
                 Ok(()) => {
-                    con.inuse();
+                    con.inuse(&self.shared, caller);
                     Ok(Some(conn))
                 }
                 Err(e) => {
-                    con.recycle();
+                    con.discard();
                     Err(e)
                 }
             }
         };
 
-        if let Some(duration) = duration {
-            match timeout(duration, fut).await {
+        let res = if let Some(duration) = duration {
+            match timeout(&*self.shared.runtime, duration, fut).await {
                 Ok(res) => res,
-                Err(_) => Err(Error::Timeout(error)),
+                Err(_) => Err(self.timed_out(error)),
             }
         } else {
             fut.await
+        };
+        res.map(|conn| conn.map(|conn| self.checked_out(conn)))
+    }
+
+    /// get an idle connection without awaiting, never connects or checks
+    ///
+    /// `None` if all in use, or the connection taken is empty or due to check(given back untouched)
+    #[track_caller]
+    pub fn try_get_now(&self) -> Result<Option<PooledConnection<M>>, Error<M::Error>> {
+        let start = self.shared.metrics.start();
+        if self.is_paused() {
+            return Ok(None);
         }
+        self.shared.sweep_due();
+        let permit = match self.shared.semaphore.wrapped_try_acquire_owned() {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                self.shared.sweep();
+                return Ok(None);
+            }
+            Err(_) => return Err(Error::Closed),
+        };
+
+        // only the connection reserved by the permit is taken, the one not ready is pushed back
+        // untouched(neither drop nor the recycle policy) before the permit released
+        let con = match self.shared.queue.pop() {
+            Some(con) if self.is_ready(&con) => con,
+            Some(con) => {
+                self.shared.queue.push(con).ok();
+                return Ok(None);
+            }
+            None => return Ok(None),
+        };
+        let mut conn = PooledConnection(Some(con));
+        let con = conn.0.as_mut().expect("try get now");
+        con.permit = Some(permit);
+        // given back on drop if the policy panics
+        if con.recycle(&self.shared) {
+            con.inuse(&self.shared, Location::caller());
+            self.shared.metrics.wait(start);
+            return Ok(Some(self.checked_out(conn)));
+        }
+        con.discard();
+        let permit = con.permit.take();
+        if let Some(con) = conn.0.take() {
+            self.shared.queue.push(con).ok();
+        }
+        drop(permit);
+        Ok(None)
+    }
+
+    // try_get connecting synchronously by `connect` instead of the manager, never awaits,
+    // for managers without check
+    pub(crate) fn try_get_with(
+        &self,
+        caller: &'static Location<'static>,
+        connect: impl FnOnce() -> Result<M::Connection, M::Error>,
+    ) -> Result<Option<PooledConnection<M>>, Error<M::Error>> {
+        let start = self.shared.metrics.start();
+        let mut conn = match self.checkout(caller, start)? {
+            Checkout::Ready(conn) => return Ok(Some(self.checked_out(conn))),
+            Checkout::Fill(conn) => conn,
+            Checkout::Busy => {
+                self.shared.sweep();
+                return Ok(None);
+            }
+        };
+
+        let con = conn.0.as_mut().expect("try get");
+        con.connecting();
+        let begin = Instant::now();
+        if let Err(e) = self.connected(con, connect(), begin) {
+            con.discard();
+            return Err(e);
+        }
+        con.inuse(&self.shared, caller);
+        Ok(Some(self.checked_out(conn)))
     }
 
     /// get with default timeout
-    pub async fn get(&self) -> Result<PooledConnection<M>, Error<M::Error>> {
-        self.get_timeout(self.config().timeout).await
+    #[track_caller]
+    pub fn get(&self) -> impl Future<Output = Result<PooledConnection<M>, Error<M::Error>>> + '_ {
+        self.get_timeout(self.config().timeout)
     }
 
     /// get with custom timeout
-    pub async fn get_timeout(
+    #[track_caller]
+    pub fn get_timeout(
         &self,
         duration: Option<Duration>,
+    ) -> impl Future<Output = Result<PooledConnection<M>, Error<M::Error>>> + '_ {
+        self.get_at(duration, Location::caller())
+    }
+
+    /// get until the deadline instead of a timeout
+    #[track_caller]
+    pub fn get_until(
+        &self,
+        deadline: Instant,
+    ) -> impl Future<Output = Result<PooledConnection<M>, Error<M::Error>>> + '_ {
+        self.get_until_at(deadline, Location::caller())
+    }
+
+    // the time left is taken when first polled
+    async fn get_until_at(
+        &self,
+        deadline: Instant,
+        caller: &'static Location<'static>,
     ) -> Result<PooledConnection<M>, Error<M::Error>> {
-        let _wait = Arc::downgrade(&self.shared.status.0);
+        let duration = deadline.saturating_duration_since(Instant::now());
+        self.get_at(Some(duration), caller).await
+    }
+
+    /// get with default timeout, gives up with `Error::Cancelled` once `cancel` completes
+    ///
+    /// `cancel` is any future, e.g. `CancellationToken::cancelled()`, a cancelled one is never checked out
+    #[track_caller]
+    pub fn get_with_cancel<'a, F>(
+        &'a self,
+        cancel: F,
+    ) -> impl Future<Output = Result<PooledConnection<M>, Error<M::Error>>> + 'a
+    where
+        F: Future<Output = ()> + 'a,
+    {
+        let get = self.get_at(self.config().timeout, Location::caller());
+        cancellable(get, cancel)
+    }
+
+    /// get until the deadline, gives up with `Error::Cancelled` once `cancel` completes
+    #[track_caller]
+    pub fn get_until_with_cancel<'a, F>(
+        &'a self,
+        deadline: Instant,
+        cancel: F,
+    ) -> impl Future<Output = Result<PooledConnection<M>, Error<M::Error>>> + 'a
+    where
+        F: Future<Output = ()> + 'a,
+    {
+        let get = self.get_until_at(deadline, Location::caller());
+        cancellable(get, cancel)
+    }
+
+    /// get and run the operation on the connection within the timeout for both
+    ///
+    /// the connection is reconnected unless the operation completes, e.g. timed out or the future dropped
+    #[track_caller]
+    pub fn with_conn<'a, R, F>(
+        &'a self,
+        duration: Option<Duration>,
+        operation: F,
+    ) -> impl Future<Output = Result<R, Error<M::Error>>> + 'a
+    where
+        F: for<'c> FnOnce(
+                &'c mut PooledConnection<M>,
+            ) -> Pin<Box<dyn Future<Output = R> + Send + 'c>>
+            + 'a,
+    {
+        let caller = Location::caller();
+        async move {
+            let deadline = duration.map(|d| Instant::now() + d);
+            let mut conn = self.get_at(duration, caller).await?;
+            conn.0.as_mut().unwrap().operating = true;
+
+            let res = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    timeout(&*self.shared.runtime, remaining, operation(&mut conn))
+                        .await
+                        .map_err(|_| Error::Timeout("operation"))?
+                }
+                None => operation(&mut conn).await,
+            };
+            conn.0.as_mut().unwrap().operating = false;
+            Ok(res)
+        }
+    }
+
+    async fn get_at(
+        &self,
+        duration: Option<Duration>,
+        caller: &'static Location<'static>,
+    ) -> Result<PooledConnection<M>, Error<M::Error>> {
+        let start = self.shared.metrics.start();
+        let conn = match self.checkout(caller, start)? {
+            Checkout::Ready(conn) => return Ok(self.checked_out(conn)),
+            Checkout::Fill(conn) => Some(conn),
+            Checkout::Busy => {
+                self.shared.sweep();
+                None
+            }
+        };
+
         let mut error = "wait";
 
-        if let Some(duration) = duration {
-            match timeout(duration, self.get_inner(&mut error)).await {
+        let res = if let Some(duration) = duration {
+            match timeout(
+                &*self.shared.runtime,
+                duration,
+                self.get_inner(&mut error, caller, conn, start),
+            )
+            .await
+            {
                 Ok(res) => res,
-                Err(_) => Err(Error::Timeout(error)),
+                Err(_) => Err(self.timed_out(error)),
             }
         } else {
-            self.get_inner(&mut error).await
-        }
+            self.get_inner(&mut error, caller, conn, start).await
+        };
+        res.map(|conn| self.checked_out(conn))
+    }
+
+    /// get with default timeout, blocks the current thread
+    ///
+    /// for threads outside of async runtime, connect and check are driven by the runtime the pool built in,
+    /// `Error::Runtime` if the pool has no runtime to drive them
+    ///
+    /// never call it on an async task, it blocks the executor thread and tokio panics with
+    /// "Cannot start a runtime from within a runtime", use `spawn_blocking` or plain threads
+    #[track_caller]
+    pub fn get_blocking(&self) -> Result<PooledConnection<M>, Error<M::Error>> {
+        self.get_blocking_timeout(self.config().timeout)
+    }
+
+    /// get with custom timeout, blocks the current thread
+    #[track_caller]
+    pub fn get_blocking_timeout(
+        &self,
+        duration: Option<Duration>,
+    ) -> Result<PooledConnection<M>, Error<M::Error>> {
+        let caller = Location::caller();
+        let mut res = None;
+        self.shared
+            .runtime
+            .block_on(Box::pin(async {
+                res = Some(self.get_at(duration, caller).await);
+            }))
+            .map_err(Error::Runtime)?;
+        res.expect("block_on")
     }
 
     async fn get_inner(
         &self,
         error: &mut &'static str,
+        caller: &'static Location<'static>,
+        mut conn: Option<PooledConnection<M>>,
+        start: Option<Instant>,
     ) -> Result<PooledConnection<M>, Error<M::Error>> {
-        let mut try_once_time = true;
-
         loop {
-            let permit = if try_once_time {
-                try_once_time = false;
-                match self.shared.semaphore.wrapped_try_acquire_owned() {
-                    Ok(Some(p)) => p,
-                    Ok(None) => continue,
-                    Err(_) => return Err(Error::Closed),
+            let mut conn = match conn.take() {
+                Some(conn) => conn,
+                None => {
+                    let wait = self.shared.status.wait();
+                    self.resumed().await?;
+                    let permit = if let Some(expires) = self.next_expires() {
+                        // wake up to revoke the expired lease if no connection returned
+                        let acquire = self.shared.semaphore.wrapped_acquire_owned();
+                        match timeout(&*self.shared.runtime, expires, acquire).await {
+                            Ok(p) => p.map_err(|_| Error::Closed)?,
+                            Err(_) => {
+                                self.shared.sweep();
+                                continue;
+                            }
+                        }
+                    } else {
+                        self.shared
+                            .semaphore
+                            .wrapped_acquire_owned()
+                            .await
+                            .map_err(|_| Error::Closed)?
+                    };
+                    drop(wait);
+
+                    match self.take_conn(permit) {
+                        Some(conn) => {
+                            self.shared.metrics.wait(start);
+                            conn
+                        }
+                        None => {
+                            yield_now().await;
+                            continue;
+                        }
+                    }
                 }
-            } else {
-                self.shared
-                    .semaphore
-                    .wrapped_acquire_owned()
-                    .await
-                    .map_err(|_| Error::Closed)?
             };
-
-            let conn = self.shared.queue.pop();
-            if conn.is_none() {
-                continue;
-            }
-
-            let mut conn = PooledConnection(conn);
             let con = conn.0.as_mut().expect("get");
-            con.permit = Some(permit);
 
             return match self.fill_conn(error, con).await {
                 Ok(()) => {
-                    con.inuse();
+                    con.inuse(&self.shared, caller);
                     Ok(conn)
                 }
                 Err(e) => {
-                    con.recycle();
+                    con.discard();
                     Err(e)
                 }
             };
         }
     }
 
+    // takes a permit and an idle connection without awaiting,
+    // the connection is checked out at once if neither connect nor check is due
+    fn checkout(
+        &self,
+        caller: &'static Location<'static>,
+        start: Option<Instant>,
+    ) -> Result<Checkout<M>, Error<M::Error>> {
+        if self.is_paused() {
+            return Ok(Checkout::Busy);
+        }
+        self.shared.sweep_due();
+        let permit = match self.shared.semaphore.wrapped_try_acquire_owned() {
+            Ok(Some(p)) => p,
+            Ok(None) => return Ok(Checkout::Busy),
+            Err(_) => return Err(Error::Closed),
+        };
+
+        let mut conn = match self.take_conn(permit) {
+            Some(conn) => conn,
+            None => return Ok(Checkout::Busy),
+        };
+        self.shared.metrics.wait(start);
+        let con = conn.0.as_mut().expect("checkout");
+        if self.is_ready(con) {
+            con.inuse(&self.shared, caller);
+            Ok(Checkout::Ready(conn))
+        } else {
+            Ok(Checkout::Fill(conn))
+        }
+    }
+
+    // pops the connection reserved by the permit, the recycle policy is consulted here once per checkout
+    //
+    // a connection is out of the queue only while a permit is held for it(by a checkout, `try_get_now`
+    // or `clear_idle`) and pushed back before the permit released, so a pop misses only when a shard
+    // scan races a push or `close` drains the queue, `None` then and the permit is released
+    fn take_conn(&self, permit: OwnedSemaphorePermit) -> Option<PooledConnection<M>> {
+        let mut conn = PooledConnection(Some(self.shared.queue.pop()?));
+        let con = conn.0.as_mut().expect("take conn");
+        con.permit = Some(permit);
+        // given back on drop if the policy panics
+        if !con.is_empty() && !con.recycle(&self.shared) {
+            con.con.take();
+        }
+        Some(conn)
+    }
+
+    fn is_ready(&self, conn: &Conn<M>) -> bool {
+        if conn.is_empty() {
+            return false;
+        }
+        match self.config().check {
+            Some(check) => {
+                check > Duration::from_secs(0) && self.shared.clock.elapsed() < (conn.time + check)
+            }
+            None => true,
+        }
+    }
+
     async fn fill_conn(
         &self,
         error: &mut &'static str,
@@ -180,11 +632,12 @@ impl<M: Manager> Pool<M> {
         let new = conn.is_empty();
         if new {
             *error = "connect";
-            let con = self.manager().connect().await?;
-            conn.con = Some(con);
+            conn.connecting();
+            let start = Instant::now();
+            let res = self.manager().connect().await;
+            self.connected(conn, res, start)?;
         }
 
-        // todo: should drop _wait while check?
         if let Some(check) = self.config().check {
             if new
                 || check == Duration::from_secs(0)
@@ -192,25 +645,126 @@ impl<M: Manager> Pool<M> {
             {
                 *error = "check";
                 conn.incheck();
-                self.manager().check(conn.con.as_mut().unwrap()).await?;
+                let start = Instant::now();
+                let res = self.manager().check(conn.con.as_mut().unwrap()).await;
+                // the same elapsed for the metrics and the error
+                let elapsed = start.elapsed();
+                self.shared.metrics.check(elapsed, res.is_ok());
+                res.map_err(|error| {
+                    self.failed(Error::Check {
+                        error,
+                        idx: conn.idx,
+                        elapsed,
+                    })
+                })?;
+                self.shared.record(Outcome::Checked);
                 conn.time = self.shared.clock.elapsed();
+                self.shared.history[conn.idx].checked(conn.time);
             }
         }
 
         Ok(())
     }
+
+    // records the connect started at `start`
+    fn connected(
+        &self,
+        conn: &mut Conn<M>,
+        res: Result<M::Connection, M::Error>,
+        start: Instant,
+    ) -> Result<(), Error<M::Error>> {
+        let elapsed = start.elapsed();
+        self.shared.metrics.connect(elapsed, res.is_ok());
+        let con = res.map_err(|error| {
+            self.failed(Error::Connect {
+                error,
+                idx: conn.idx,
+                elapsed,
+            })
+        })?;
+        self.shared.record(Outcome::Connected);
+        conn.con = Some(con);
+        conn.connected = self.shared.clock.elapsed();
+        self.shared.history[conn.idx].connected(conn.connected);
+        conn.uses = 0;
+        conn.held = Duration::from_secs(0);
+        conn.error = None;
+        Ok(())
+    }
+
+    fn failed(&self, e: Error<M::Error>) -> Error<M::Error> {
+        self.shared.record(Outcome::Failed);
+        e
+    }
+
+    fn timed_out(&self, error: &'static str) -> Error<M::Error> {
+        self.shared.record(Outcome::Timeout);
+        self.shared.metrics.timeout();
+        Error::Timeout(error)
+    }
+
+    fn checked_out(&self, conn: PooledConnection<M>) -> PooledConnection<M> {
+        self.shared.metrics.checkout();
+        self.shared.metrics.publish(&self.shared.status);
+        conn
+    }
+
+    // the time left before the earliest lease revoked
+    fn next_expires(&self) -> Option<Duration> {
+        let max_hold = self.config().max_hold_time?;
+        let now = self.shared.clock.elapsed();
+        self.shared
+            .leases
+            .iter()
+            .filter_map(|lease| lease.lock().unwrap().expires(now, max_hold))
+            .min()
+    }
+}
+
+// the checkout is dropped once `cancel` completes
+async fn cancellable<T, E>(
+    get: impl Future<Output = Result<T, Error<E>>>,
+    cancel: impl Future<Output = ()>,
+) -> Result<T, Error<E>> {
+    use futures_util::future::{select, Either};
+
+    futures_util::pin_mut!(cancel, get);
+    match select(cancel, get).await {
+        Either::Left(_) => Err(Error::Cancelled),
+        Either::Right((res, _)) => res,
+    }
 }
 
 impl<M: Manager> Drop for Pool<M> {
     fn drop(&mut self) {
-        self.shared.semaphore.close();
+        self.close();
     }
 }
-#[derive(Clone, Debug)]
+
+// the result of checkout without awaiting
+enum Checkout<M: Manager> {
+    // in use already
+    Ready(PooledConnection<M>),
+    // holds the permit, needs connect or check
+    Fill(PooledConnection<M>),
+    // no permit
+    Busy,
+}
+#[derive(Clone)]
 pub struct Builder {
     maxsize: usize,
     check: Option<Duration>,
     timeout: Option<Duration>,
+    recycle: Option<Arc<dyn RecyclePolicy>>,
+    leak_detection: Option<Duration>,
+    leak_backtrace: bool,
+    leak_observer: Option<Arc<dyn LeakObserver>>,
+    max_hold_time: Option<Duration>,
+    discard_on_panic: bool,
+    runtime: Option<Arc<dyn Runtime>>,
+    shards: usize,
+    name: Option<String>,
+    registry: Option<Arc<PoolRegistry>>,
 }
 
 impl Default for Builder {
@@ -219,10 +773,40 @@ impl Default for Builder {
             maxsize: 20,
             timeout: Some(Duration::from_secs(5)),
             check: Some(Duration::from_secs(0)),
+            recycle: None,
+            leak_detection: None,
+            leak_backtrace: false,
+            leak_observer: None,
+            max_hold_time: None,
+            discard_on_panic: true,
+            runtime: None,
+            shards: 1,
+            name: None,
+            registry: None,
         }
     }
 }
 
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("maxsize", &self.maxsize)
+            .field("check", &self.check)
+            .field("timeout", &self.timeout)
+            .field("recycle", &self.recycle.as_ref().map(|_| ()))
+            .field("leak_detection", &self.leak_detection)
+            .field("leak_backtrace", &self.leak_backtrace)
+            .field("leak_observer", &self.leak_observer.as_ref().map(|_| ()))
+            .field("max_hold_time", &self.max_hold_time)
+            .field("discard_on_panic", &self.discard_on_panic)
+            .field("runtime", &self.runtime.as_ref().map(|_| ()))
+            .field("shards", &self.shards)
+            .field("name", &self.name)
+            .field("registry", &self.registry.as_ref().map(|_| ()))
+            .finish()
+    }
+}
+
 impl Builder {
     pub fn maxsize(mut self, maxsize: usize) -> Self {
         assert!(maxsize > 0);
@@ -235,6 +819,8 @@ impl Builder {
     ///
     /// `>0` => check every duration
     ///
+    /// an idle connection is checked out without awaiting only when no check is due,
+    /// never with `0`
     pub fn check(mut self, check_duration: Option<Duration>) -> Self {
         self.check = check_duration;
         self
@@ -244,6 +830,86 @@ impl Builder {
         self.timeout = timeout;
         self
     }
+    /// decide whether to keep a connection when it's returned and before it's checked out
+    pub fn recycle_policy<P: RecyclePolicy>(mut self, policy: P) -> Self {
+        self.recycle = Some(Arc::new(policy));
+        self
+    }
+    /// report connections held longer than the threshold, `None` => disabled
+    ///
+    /// reported to `log` or the `on_leak` observer when the pool runs out of connections
+    pub fn leak_detection(mut self, threshold: Option<Duration>) -> Self {
+        self.leak_detection = threshold;
+        self
+    }
+    /// capture a backtrace at checkout for leak reports, it's expensive
+    pub fn leak_backtrace(mut self, backtrace: bool) -> Self {
+        self.leak_backtrace = backtrace;
+        self
+    }
+    /// report leaked connections to the observer instead of `log`
+    pub fn on_leak<O: LeakObserver>(mut self, observer: O) -> Self {
+        self.leak_observer = Some(Arc::new(observer));
+        self
+    }
+    /// revoke connections held longer than the duration, `None` => never
+    ///
+    /// the slot is reconnected for waiters, the stale `PooledConnection` is discarded on drop
+    pub fn max_hold_time(mut self, max_hold_time: Option<Duration>) -> Self {
+        self.max_hold_time = max_hold_time;
+        self
+    }
+    /// discard the connections dropped while the thread panicking instead of returning them idle, default true
+    ///
+    /// the futures dropped by task abort don't panic, see `Pool::with_conn` for them
+    pub fn discard_on_panic(mut self, discard_on_panic: bool) -> Self {
+        self.discard_on_panic = discard_on_panic;
+        self
+    }
+    /// the runtime for timers and blocking checkout
+    ///
+    /// default to `TokioRuntime::current()` when built in a tokio runtime(timers in the runtime polling
+    /// them, blocking checkout on the runtime built in), otherwise the first enabled of
+    /// `AsyncStdRuntime`, `SmolRuntime` and `TokioRuntime::current()`
+    pub fn runtime<R: Runtime>(mut self, runtime: R) -> Self {
+        self.runtime = Some(Arc::new(runtime));
+        self
+    }
+    /// the name of the pool in the registry and the `pool` label of metrics, default to `flexc`
+    ///
+    /// with the metrics feature, the metrics are registered to the recorder installed when the pool
+    /// is built, a pool built before the recorder installed(e.g. a static one) reports nothing
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    /// register the pool by the name when built, replacing the pool of the same name
+    ///
+    /// `PoolRegistry::global()` for the registry of the process
+    pub fn registry(mut self, registry: Arc<PoolRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+    /// split idle connections into shards by thread to cut contention on many cores, default 1
+    ///
+    /// a thread takes from its own shard first and steals from the others when it's empty,
+    /// the number of cores is a good choice, at most `maxsize`
+    pub fn shards(mut self, shards: usize) -> Self {
+        assert!(shards > 0);
+        self.shards = shards;
+        self
+    }
+
+    pub(crate) fn pool_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("flexc")
+    }
+
+    pub(crate) fn runtime_or_default(&self) -> Arc<dyn Runtime> {
+        self.runtime
+            .clone()
+            .or_else(runtime::default_runtime)
+            .expect("flexc: no runtime, enable a runtime feature or set Builder::runtime")
+    }
 
     /// Consumes the builder, returning a new Pool
     pub fn build_unchecked<M: Manager>(self, manager: M) -> Pool<M> {
@@ -254,6 +920,11 @@ impl Builder {
             shared.queue.push(conn).ok();
         }
 
+        if let Some(registry) = shared.cfg.registry.as_ref() {
+            let control: Arc<dyn PoolControl> = shared.clone();
+            registry.insert(shared.cfg.pool_name().to_owned(), Arc::downgrade(&control));
+        }
+
         Pool { shared }
     }
 
@@ -269,25 +940,121 @@ pub(crate) struct SharedPool<M: Manager> {
     cfg: Builder,
     manager: M,
     semaphore: Arc<Semaphore>,
-    queue: ArrayQueue<Conn<M>>,
+    queue: Shards<Conn<M>>,
     status: Status,
+    // the leases are only kept if tracking
+    leases: Vec<Mutex<Lease>>,
+    tracking: bool,
+    next_sweep: AtomicU64,
+    history: Vec<CachePadded<History>>,
+    runtime: Arc<dyn Runtime>,
     clock: Instant,
+    paused: AtomicBool,
+    resumed: Event,
+    outcomes: Outcomes,
+    metrics: Metrics,
 }
 
 impl<M: Manager> SharedPool<M> {
     pub(crate) fn new(cfg: Builder, manager: M) -> Self {
         let semaphore = Semaphore::wrapped_new(cfg.maxsize);
-        let queue = ArrayQueue::new(cfg.maxsize);
+        let queue = Shards::new(cfg.shards, cfg.maxsize);
         let status = Status::new(cfg.maxsize);
+        let leases = (0..cfg.maxsize).map(|_| Mutex::default()).collect();
+        let tracking = cfg.leak_detection.is_some() || cfg.max_hold_time.is_some();
+        let history = (0..cfg.maxsize).map(|_| CachePadded::default()).collect();
+        let runtime = cfg.runtime_or_default();
+        let metrics = Metrics::new(cfg.pool_name());
         Self {
             cfg,
             manager,
             status,
             queue,
+            leases,
+            tracking,
+            next_sweep: AtomicU64::new(0),
+            history,
+            runtime,
             semaphore,
             clock: Instant::now(),
+            paused: AtomicBool::new(false),
+            resumed: Event::new(),
+            outcomes: Outcomes::default(),
+            metrics,
+        }
+    }
+
+    // report the connections held longer than leak_detection(each lease once),
+    // and revoke the connections held longer than max_hold_time
+    fn sweep(self: &Arc<Self>) {
+        if !self.tracking {
+            return;
+        }
+
+        let cfg = &self.cfg;
+        let now = self.clock.elapsed();
+        let mut leaked = vec![];
+        for (idx, lease) in self.leases.iter().enumerate() {
+            let mut lease = lease.lock().unwrap();
+            if let Some(threshold) = cfg.leak_detection {
+                leaked.extend(lease.leaked(idx, now, threshold));
+            }
+            if let Some(max_hold) = cfg.max_hold_time {
+                if lease.expires(now, max_hold) == Some(Duration::from_secs(0)) {
+                    log::warn!(
+                        "flexc: connection-{} revoked after held for {:?}",
+                        idx,
+                        max_hold
+                    );
+                    let permit = lease.revoke();
+                    let mut conn = Conn::new(idx, self);
+                    conn.generation = lease.generation;
+                    drop(lease);
+
+                    self.status.set_empty(idx);
+                    self.queue.push(conn).ok();
+                    drop(permit);
+                }
+            }
+        }
+
+        for held in &leaked {
+            match cfg.leak_observer.as_ref() {
+                Some(observer) => observer.leaked(held),
+                None => lease::report(held),
+            }
+        }
+    }
+
+    // sweep at most once in a quarter of the thresholds, on checkout and return
+    fn sweep_due(self: &Arc<Self>) {
+        let interval = match (self.cfg.leak_detection, self.cfg.max_hold_time) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => match a.or(b) {
+                Some(threshold) => threshold,
+                None => return,
+            },
+        } / 4;
+        let now = self.clock.elapsed().as_nanos() as u64;
+        let next = self.next_sweep.load(Ordering::Relaxed);
+        if now >= next
+            && self
+                .next_sweep
+                .compare_exchange(
+                    next,
+                    now + interval.as_nanos() as u64,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            self.sweep();
         }
     }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes.record(self.clock.elapsed(), outcome);
+    }
 }
 
 #[async_trait]
@@ -306,6 +1073,13 @@ pub trait Manager: Send + Sync + 'static {
     /// A standard implementation would check if a simple query like `SELECT 1`
     /// succeeds.
     async fn check(&self, conn: &mut Self::Connection) -> Result<(), Self::Error>;
+
+    /// Resets the connection given back by `PooledConnection::release`, e.g. rollback.
+    ///
+    /// The connection is discarded if fails.
+    async fn reset(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 /// A smart pointer wrapping a connection.
 #[derive(Debug)]
@@ -314,11 +1088,20 @@ pub struct PooledConnection<M: Manager>(Option<Conn<M>>);
 pub(crate) struct Conn<M: Manager> {
     idx: usize,
     time: Duration,
+    connected: Duration,
+    // set by a user checkout, taken on drop
+    checkout: Option<Duration>,
+    held: Duration,
+    uses: u64,
+    error: Option<Duration>,
     status: Status,
     shared: Weak<SharedPool<M>>,
     con: Option<M::Connection>,
     permit: Option<OwnedSemaphorePermit>,
+    generation: u64,
     reconnect: bool,
+    // in the operation of `Pool::with_conn`, discarded on drop
+    operating: bool,
 }
 
 impl<M: Manager> fmt::Debug for Conn<M> {
@@ -326,7 +1109,9 @@ impl<M: Manager> fmt::Debug for Conn<M> {
         f.debug_struct("Conn")
             .field("idx", &self.idx)
             .field("time", &self.time)
-            .field("state", &self.status.0[self.idx])
+            .field("connected", &self.connected)
+            .field("uses", &self.uses)
+            .field("state", &self.status.get(self.idx))
             .field("con", &self.con.as_ref().map(|_| ()))
             .field("permit", &self.con.as_ref().map(|_| ()))
             .field("shared", &"..")
@@ -341,9 +1126,16 @@ impl<M: Manager> Conn<M> {
             shared: Arc::downgrade(shared),
             status: shared.status.clone(),
             time: Duration::from_secs(0),
+            connected: Duration::from_secs(0),
+            checkout: None,
+            held: Duration::from_secs(0),
+            uses: 0,
+            error: None,
             con: None,
             permit: None,
+            generation: 0,
             reconnect: false,
+            operating: false,
         }
     }
     pub(crate) fn is_empty(&self) -> bool {
@@ -355,11 +1147,43 @@ impl<M: Manager> Conn<M> {
     pub(crate) fn incheck(&mut self) {
         self.status.set_incheck(self.idx);
     }
-    pub(crate) fn inuse(&mut self) {
+    pub(crate) fn connecting(&mut self) {
+        self.status.set_connecting(self.idx);
+    }
+    pub(crate) fn inuse(&mut self, shared: &SharedPool<M>, caller: &'static Location<'static>) {
+        let now = shared.clock.elapsed();
+        let backtrace = shared.cfg.leak_detection.is_some() && shared.cfg.leak_backtrace;
+        self.checkout = Some(now);
+        self.uses += 1;
+        shared.history[self.idx].checkout(now, self.uses);
+        if shared.tracking {
+            shared.leases[self.idx].lock().unwrap().hold(
+                caller,
+                now,
+                backtrace,
+                self.permit.take(),
+            );
+        }
         self.status.set_inuse(self.idx);
     }
-    // recycle when connect/check error
-    pub(crate) fn recycle(&mut self) {
+    pub(crate) fn info(&self, now: Duration) -> ConnInfo {
+        ConnInfo {
+            idx: self.idx,
+            age: now - self.connected,
+            uses: self.uses,
+            held: self.held,
+            last_error: self.error.map(|e| now - e),
+        }
+    }
+    // ask the recycle policy whether to keep the connection
+    pub(crate) fn recycle(&self, shared: &SharedPool<M>) -> bool {
+        match shared.cfg.recycle.as_ref() {
+            Some(policy) => policy.recycle(&self.info(shared.clock.elapsed())),
+            None => true,
+        }
+    }
+    // discard when connect/check error
+    pub(crate) fn discard(&mut self) {
         self.con.take();
         self.status.set_empty(self.idx);
     }
@@ -377,6 +1201,42 @@ impl<M: Manager> PooledConnection<M> {
     pub fn reconnect(&self) -> bool {
         self.0.as_ref().unwrap().reconnect
     }
+    /// the connection was held longer than `Builder::max_hold_time` and revoked by the pool,
+    /// it will be discarded on drop
+    pub fn revoked(&self) -> bool {
+        let conn = self.0.as_ref().unwrap();
+        match conn.shared.upgrade() {
+            Some(p) if p.tracking => {
+                p.leases[conn.idx].lock().unwrap().generation != conn.generation
+            }
+            _ => false,
+        }
+    }
+    /// record an error of the connection, the time is passed to the recycle policy
+    pub fn report_error(&mut self) {
+        let conn = self.0.as_mut().unwrap();
+        if let Some(p) = conn.shared.upgrade() {
+            conn.error = Some(p.clock.elapsed());
+        }
+    }
+    /// give the connection back to the pool after `Manager::reset`, discarded if the reset fails
+    ///
+    /// dropping does the same without the reset
+    pub async fn release(mut self) -> Result<(), Error<M::Error>> {
+        let shared = match self.0.as_ref().unwrap().shared.upgrade() {
+            Some(p) if !self.reconnect() && !self.revoked() => p,
+            _ => return Ok(()),
+        };
+        // discarded on drop if the reset is cancelled
+        self.set_reconnect(true);
+        shared.manager.reset(self.as_mut()).await?;
+        self.set_reconnect(false);
+        Ok(())
+    }
+    /// give the slot back to the pool without the connection
+    pub fn discard(mut self) {
+        self.set_reconnect(true);
+    }
 }
 
 impl<M: Manager> AsRef<M::Connection> for PooledConnection<M> {
@@ -406,14 +1266,54 @@ impl<M: Manager> std::ops::DerefMut for PooledConnection<M> {
 
 impl<M: Manager> Drop for PooledConnection<M> {
     fn drop(&mut self) {
-        let mut conn = self.0.take().unwrap();
+        let mut conn = match self.0.take() {
+            Some(conn) => conn,
+            // taken back by try_get_now
+            None => return,
+        };
+        let shared = conn.shared.upgrade();
+
+        if let Some(p) = shared.as_ref().filter(|p| p.tracking) {
+            let mut lease = p.leases[conn.idx].lock().unwrap();
+            if lease.generation != conn.generation {
+                // revoked, the slot was already given back to the pool
+                return;
+            }
+            if let Some(permit) = lease.release() {
+                conn.permit = Some(permit);
+            }
+        }
 
-        if conn.is_incheck() || conn.reconnect {
-            //  check failed when get-xxx timeout(inner future dropped, recycle not run), should giveup the connection
+        let panicking =
+            std::thread::panicking() && shared.as_ref().map_or(false, |p| p.cfg.discard_on_panic);
+        if conn.is_incheck() || conn.reconnect || conn.operating || panicking {
+            //  check failed when get-xxx timeout(inner future dropped, discard not run), should giveup the connection
             conn.con.take();
         }
+        let mut policy_panic = None;
+        if let Some(p) = shared.as_ref() {
+            // the policy is consulted only when returned by a user, a connection filled
+            // but never checked out keeps the held of the last user
+            if let Some(checkout) = conn.checkout.take() {
+                conn.held = p.clock.elapsed() - checkout;
+                // a panicking policy discards the connection, the panic resumes after it's given back
+                let recycle = catch_unwind(AssertUnwindSafe(|| conn.is_empty() || conn.recycle(p)));
+                match recycle {
+                    Ok(true) => {}
+                    Ok(false) => drop(conn.con.take()),
+                    Err(payload) => {
+                        conn.con.take();
+                        policy_panic = Some(payload);
+                    }
+                }
+            }
+            if SemaphoreWrap::is_closed(&*p.semaphore) {
+                conn.con.take();
+            }
+        }
         // reset
         conn.reconnect = false;
+        conn.operating = false;
 
         if conn.is_empty() {
             conn.status.set_empty(conn.idx);
@@ -422,9 +1322,17 @@ impl<M: Manager> Drop for PooledConnection<M> {
         }
 
         // the pool not dropped
-        if let Some(p) = conn.shared.upgrade() {
-            conn.permit.take();
+        if let Some(p) = shared {
+            // pushed before the permit released, a waiter woken by the permit always finds it
+            let permit = conn.permit.take();
             p.queue.push(conn).ok();
+            drop(permit);
+            p.metrics.publish(&p.status);
+            p.sweep_due();
+        }
+
+        if let Some(payload) = policy_panic.filter(|_| !std::thread::panicking()) {
+            resume_unwind(payload);
         }
     }
 }
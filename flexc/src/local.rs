@@ -0,0 +1,515 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::runtime::{timeout, Runtime};
+use crate::status::{STATUS_CONNECTING, STATUS_EMPTY, STATUS_IDLE, STATUS_INCHECK, STATUS_INUSE};
+use crate::{async_trait, Builder, ConnInfo, Error, State};
+
+#[async_trait(?Send)]
+/// A trait which provides connection-specific functionality for `LocalPool`, the connection needn't be `Send`.
+pub trait LocalManager: 'static {
+    /// The connection type this manager deals with.
+    type Connection: 'static;
+    /// The error type returned by `Connection`s.
+    type Error: 'static;
+
+    /// Attempts to create a new connection.
+    async fn connect(&self) -> Result<Self::Connection, Self::Error>;
+
+    /// Determines if the connection is still connected to the database when check-out.
+    async fn check(&self, conn: &mut Self::Connection) -> Result<(), Self::Error>;
+}
+
+/// A pool for the single-threaded executors, such as `tokio::task::LocalSet`.
+///
+/// Same as `Pool` without `Send` bounds, the leak detection and `max_hold_time` of `Builder` are ignored.
+pub struct LocalPool<M: LocalManager> {
+    shared: Rc<LocalShared<M>>,
+}
+
+struct LocalShared<M: LocalManager> {
+    cfg: Builder,
+    manager: M,
+    idle: RefCell<Vec<LocalConn<M>>>,
+    status: Vec<Cell<u8>>,
+    // the tasks waiting for idle connection with the time they began, the first key is the oldest one
+    waiters: RefCell<BTreeMap<u64, (Instant, Waker)>>,
+    waiter_id: Cell<u64>,
+    closed: Cell<bool>,
+    runtime: Arc<dyn Runtime>,
+    clock: Instant,
+}
+
+impl<M: LocalManager> LocalShared<M> {
+    fn wake_first(&self) {
+        if let Some((_, waker)) = self.waiters.borrow().values().next() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl<M: LocalManager> LocalPool<M> {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    pub fn state(&self) -> State {
+        let waiters = self.shared.waiters.borrow();
+        let mut state = State {
+            maxsize: self.shared.status.len() as _,
+            wait: waiters.len() as _,
+            oldest_wait: waiters.values().next().map(|(start, _)| start.elapsed()),
+            ..Default::default()
+        };
+
+        for s in &self.shared.status {
+            match s.get() {
+                STATUS_EMPTY => state.empty += 1,
+                STATUS_CONNECTING => state.connecting += 1,
+                STATUS_INCHECK => state.incheck += 1,
+                STATUS_INUSE => state.inuse += 1,
+                STATUS_IDLE => state.idle += 1,
+                invalid => unreachable!("invalid status: {}", invalid),
+            }
+        }
+        state.size = state.inuse + state.idle + state.incheck;
+
+        state
+    }
+
+    pub fn manager(&self) -> &M {
+        &self.shared.manager
+    }
+
+    pub fn config(&self) -> &Builder {
+        &self.shared.cfg
+    }
+
+    /// close the pool, the waiters get `Error::Closed`, idle connections are dropped
+    /// and the connections in use are dropped when returned
+    pub fn close(&self) {
+        self.shared.closed.set(true);
+        for conn in self.shared.idle.borrow_mut().iter_mut() {
+            conn.discard();
+        }
+        for (_, waker) in self.shared.waiters.borrow().values() {
+            waker.wake_by_ref();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.get()
+    }
+
+    /// start max-size connections
+    pub async fn start_connections(&self) -> Result<usize, Error<M::Error>> {
+        let futs = (0..self.config().maxsize).map(|_| self.get());
+        let conns = futures_util::future::join_all(futs).await;
+
+        let mut count = 0;
+        for res in conns {
+            res?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// get without waiting idle connection, default timeout is for connect and check
+    pub async fn try_get(&self) -> Result<Option<LocalPooledConnection<M>>, Error<M::Error>> {
+        self.try_get_timeout(self.config().timeout).await
+    }
+
+    /// get without waiting idle connection, custom timeout is for connect and check
+    pub async fn try_get_timeout(
+        &self,
+        duration: Option<Duration>,
+    ) -> Result<Option<LocalPooledConnection<M>>, Error<M::Error>> {
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+        if !self.shared.waiters.borrow().is_empty() {
+            return Ok(None);
+        }
+        let conn = match self.shared.idle.borrow_mut().pop() {
+            Some(conn) => LocalPooledConnection(Some(conn)),
+            None => return Ok(None),
+        };
+
+        let mut error = "wait";
+        let fut = self.fill_conn(&mut error, conn);
+        let res = match duration {
+            Some(duration) => match timeout(&*self.shared.runtime, duration, fut).await {
+                Ok(res) => res,
+                Err(_) => Err(Error::Timeout(error)),
+            },
+            None => fut.await,
+        };
+        res.map(Some)
+    }
+
+    /// get with default timeout
+    pub async fn get(&self) -> Result<LocalPooledConnection<M>, Error<M::Error>> {
+        self.get_timeout(self.config().timeout).await
+    }
+
+    /// get with custom timeout
+    pub async fn get_timeout(
+        &self,
+        duration: Option<Duration>,
+    ) -> Result<LocalPooledConnection<M>, Error<M::Error>> {
+        let mut error = "wait";
+
+        if let Some(duration) = duration {
+            match timeout(&*self.shared.runtime, duration, self.get_inner(&mut error)).await {
+                Ok(res) => res,
+                Err(_) => Err(Error::Timeout(error)),
+            }
+        } else {
+            self.get_inner(&mut error).await
+        }
+    }
+
+    async fn get_inner(
+        &self,
+        error: &mut &'static str,
+    ) -> Result<LocalPooledConnection<M>, Error<M::Error>> {
+        let acquire = Acquire {
+            shared: &self.shared,
+            id: None,
+        };
+        let conn = LocalPooledConnection(Some(acquire.await?));
+
+        self.fill_conn(error, conn).await
+    }
+
+    async fn fill_conn(
+        &self,
+        error: &mut &'static str,
+        mut pc: LocalPooledConnection<M>,
+    ) -> Result<LocalPooledConnection<M>, Error<M::Error>> {
+        let shared = &self.shared;
+        let conn = pc.0.as_mut().expect("fill conn");
+
+        if !conn.is_empty() && !conn.recycle(shared) {
+            conn.con.take();
+        }
+
+        let new = conn.is_empty();
+        if new {
+            *error = "connect";
+            conn.set_status(STATUS_CONNECTING);
+            let start = Instant::now();
+            match self.manager().connect().await {
+                Ok(con) => {
+                    conn.con = Some(con);
+                    conn.connected = shared.clock.elapsed();
+                    conn.uses = 0;
+                    conn.held = Duration::from_secs(0);
+                }
+                Err(error) => {
+                    conn.discard();
+                    return Err(Error::Connect {
+                        error,
+                        idx: conn.idx,
+                        elapsed: start.elapsed(),
+                    });
+                }
+            }
+        }
+
+        if let Some(check) = self.config().check {
+            if new
+                || check == Duration::from_secs(0)
+                || shared.clock.elapsed() >= (conn.time + check)
+            {
+                *error = "check";
+                conn.set_status(STATUS_INCHECK);
+                let start = Instant::now();
+                if let Err(error) = self.manager().check(conn.con.as_mut().unwrap()).await {
+                    conn.discard();
+                    return Err(Error::Check {
+                        error,
+                        idx: conn.idx,
+                        elapsed: start.elapsed(),
+                    });
+                }
+                conn.time = shared.clock.elapsed();
+            }
+        }
+
+        conn.uses += 1;
+        conn.checkout = Some(shared.clock.elapsed());
+        conn.set_status(STATUS_INUSE);
+        Ok(pc)
+    }
+}
+
+impl<M: LocalManager> Drop for LocalPool<M> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Builder {
+    /// Consumes the builder, returning a new LocalPool
+    pub fn build_local_unchecked<M: LocalManager>(self, manager: M) -> LocalPool<M> {
+        let maxsize = self.maxsize;
+        let runtime = self.runtime_or_default();
+        let shared = Rc::new(LocalShared {
+            cfg: self,
+            manager,
+            idle: RefCell::new(Vec::with_capacity(maxsize)),
+            status: (0..maxsize).map(|_| Cell::new(STATUS_EMPTY)).collect(),
+            waiters: RefCell::default(),
+            waiter_id: Cell::new(0),
+            closed: Cell::new(false),
+            runtime,
+            clock: Instant::now(),
+        });
+
+        shared
+            .idle
+            .borrow_mut()
+            .extend((0..maxsize).rev().map(|idx| LocalConn::new(idx, &shared)));
+
+        LocalPool { shared }
+    }
+
+    /// build_local_unchecked and start max-size connections
+    pub async fn build_local<M: LocalManager>(
+        self,
+        manager: M,
+    ) -> Result<LocalPool<M>, Error<M::Error>> {
+        let this = self.build_local_unchecked(manager);
+        this.start_connections().await?;
+        Ok(this)
+    }
+}
+
+// waits for an idle connection in FIFO order
+struct Acquire<'a, M: LocalManager> {
+    shared: &'a LocalShared<M>,
+    id: Option<u64>,
+}
+
+impl<M: LocalManager> Future for Acquire<'_, M> {
+    type Output = Result<LocalConn<M>, Error<M::Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let shared = this.shared;
+
+        if shared.closed.get() {
+            return Poll::Ready(Err(Error::Closed));
+        }
+
+        let mut waiters = shared.waiters.borrow_mut();
+        let first = match this.id {
+            Some(id) => waiters.keys().next() == Some(&id),
+            None => waiters.is_empty(),
+        };
+        if first {
+            let mut idle = shared.idle.borrow_mut();
+            if let Some(conn) = idle.pop() {
+                if let Some(id) = this.id.take() {
+                    waiters.remove(&id);
+                }
+                if !idle.is_empty() {
+                    drop(idle);
+                    drop(waiters);
+                    shared.wake_first();
+                }
+                return Poll::Ready(Ok(conn));
+            }
+        }
+
+        let id = *this.id.get_or_insert_with(|| {
+            let id = shared.waiter_id.get();
+            shared.waiter_id.set(id + 1);
+            id
+        });
+        // keep the time of the first poll
+        let start = waiters
+            .get(&id)
+            .map_or_else(Instant::now, |(start, _)| *start);
+        waiters.insert(id, (start, cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+impl<M: LocalManager> Drop for Acquire<'_, M> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.shared.waiters.borrow_mut().remove(&id);
+            // pass the wakeup to the next waiter
+            if !self.shared.idle.borrow().is_empty() {
+                self.shared.wake_first();
+            }
+        }
+    }
+}
+
+/// A smart pointer wrapping a connection of `LocalPool`.
+#[derive(Debug)]
+pub struct LocalPooledConnection<M: LocalManager>(Option<LocalConn<M>>);
+
+struct LocalConn<M: LocalManager> {
+    idx: usize,
+    time: Duration,
+    connected: Duration,
+    // set by a user checkout, taken on drop
+    checkout: Option<Duration>,
+    held: Duration,
+    uses: u64,
+    shared: Weak<LocalShared<M>>,
+    con: Option<M::Connection>,
+    reconnect: bool,
+}
+
+impl<M: LocalManager> fmt::Debug for LocalConn<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalConn")
+            .field("idx", &self.idx)
+            .field("time", &self.time)
+            .field("connected", &self.connected)
+            .field("uses", &self.uses)
+            .field("con", &self.con.as_ref().map(|_| ()))
+            .field("shared", &"..")
+            .finish()
+    }
+}
+
+impl<M: LocalManager> LocalConn<M> {
+    fn new(idx: usize, shared: &Rc<LocalShared<M>>) -> Self {
+        Self {
+            idx,
+            time: Duration::from_secs(0),
+            connected: Duration::from_secs(0),
+            checkout: None,
+            held: Duration::from_secs(0),
+            uses: 0,
+            shared: Rc::downgrade(shared),
+            con: None,
+            reconnect: false,
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.con.is_none()
+    }
+    fn set_status(&self, status: u8) {
+        if let Some(p) = self.shared.upgrade() {
+            p.status[self.idx].set(status);
+        }
+    }
+    fn discard(&mut self) {
+        self.con.take();
+        self.set_status(STATUS_EMPTY);
+    }
+    fn recycle(&self, shared: &LocalShared<M>) -> bool {
+        let now = shared.clock.elapsed();
+        match shared.cfg.recycle.as_ref() {
+            Some(policy) => policy.recycle(&ConnInfo {
+                idx: self.idx,
+                age: now - self.connected,
+                uses: self.uses,
+                held: self.held,
+                last_error: None,
+            }),
+            None => true,
+        }
+    }
+}
+
+impl<M: LocalManager> LocalPooledConnection<M> {
+    /// Take this connection from the pool permanently.
+    pub fn take(mut self) -> M::Connection {
+        self.0.as_mut().unwrap().con.take().unwrap()
+    }
+    /// the pool will reconnect the connection after recycle it if reconnect
+    pub fn set_reconnect(&mut self, reconnect: bool) {
+        self.0.as_mut().unwrap().reconnect = reconnect;
+    }
+    pub fn reconnect(&self) -> bool {
+        self.0.as_ref().unwrap().reconnect
+    }
+}
+
+impl<M: LocalManager> AsRef<M::Connection> for LocalPooledConnection<M> {
+    fn as_ref(&self) -> &M::Connection {
+        self.0.as_ref().unwrap().con.as_ref().unwrap()
+    }
+}
+
+impl<M: LocalManager> AsMut<M::Connection> for LocalPooledConnection<M> {
+    fn as_mut(&mut self) -> &mut M::Connection {
+        self.0.as_mut().unwrap().con.as_mut().unwrap()
+    }
+}
+
+impl<M: LocalManager> std::ops::Deref for LocalPooledConnection<M> {
+    type Target = M::Connection;
+    fn deref(&self) -> &Self::Target {
+        self.as_ref()
+    }
+}
+
+impl<M: LocalManager> std::ops::DerefMut for LocalPooledConnection<M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.as_mut()
+    }
+}
+
+impl<M: LocalManager> Drop for LocalPooledConnection<M> {
+    fn drop(&mut self) {
+        let mut conn = self.0.take().unwrap();
+        let shared = match conn.shared.upgrade() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let incheck = shared.status[conn.idx].get() == STATUS_INCHECK;
+        let panicking = std::thread::panicking() && shared.cfg.discard_on_panic;
+        if incheck || conn.reconnect || panicking || shared.closed.get() {
+            conn.con.take();
+        }
+        let mut policy_panic = None;
+        // the policy is consulted only when returned by a user
+        if let Some(checkout) = conn.checkout.take() {
+            conn.held = shared.clock.elapsed() - checkout;
+            // a panicking policy discards the connection, the panic resumes after it's given back
+            match catch_unwind(AssertUnwindSafe(|| {
+                conn.is_empty() || conn.recycle(&shared)
+            })) {
+                Ok(true) => {}
+                Ok(false) => drop(conn.con.take()),
+                Err(payload) => {
+                    conn.con.take();
+                    policy_panic = Some(payload);
+                }
+            }
+        }
+        conn.reconnect = false;
+
+        if conn.is_empty() {
+            conn.set_status(STATUS_EMPTY);
+        } else {
+            conn.set_status(STATUS_IDLE);
+        }
+
+        shared.idle.borrow_mut().push(conn);
+        shared.wake_first();
+
+        if let Some(payload) = policy_panic.filter(|_| !std::thread::panicking()) {
+            resume_unwind(payload);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use crate::status::Status;
+
+// the handles registered once by pool name, to the recorder installed when the pool is built
+#[cfg(feature = "metrics")]
+pub(crate) struct Metrics {
+    maxsize: metrics::Gauge,
+    empty: metrics::Gauge,
+    connecting: metrics::Gauge,
+    size: metrics::Gauge,
+    inuse: metrics::Gauge,
+    idle: metrics::Gauge,
+    incheck: metrics::Gauge,
+    wait: metrics::Gauge,
+    checkouts: metrics::Counter,
+    timeouts: metrics::Counter,
+    connect_failures: metrics::Counter,
+    check_failures: metrics::Counter,
+    wait_seconds: metrics::Histogram,
+    connect_seconds: metrics::Histogram,
+    check_seconds: metrics::Histogram,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    pub(crate) fn new(name: &str) -> Self {
+        use metrics::{counter, gauge, histogram};
+
+        let name = name.to_owned();
+        Self {
+            maxsize: gauge!("flexc_maxsize", "pool" => name.clone()),
+            empty: gauge!("flexc_empty", "pool" => name.clone()),
+            connecting: gauge!("flexc_connecting", "pool" => name.clone()),
+            size: gauge!("flexc_size", "pool" => name.clone()),
+            inuse: gauge!("flexc_inuse", "pool" => name.clone()),
+            idle: gauge!("flexc_idle", "pool" => name.clone()),
+            incheck: gauge!("flexc_incheck", "pool" => name.clone()),
+            wait: gauge!("flexc_wait", "pool" => name.clone()),
+            checkouts: counter!("flexc_checkouts_total", "pool" => name.clone()),
+            timeouts: counter!("flexc_timeouts_total", "pool" => name.clone()),
+            connect_failures: counter!("flexc_connect_failures_total", "pool" => name.clone()),
+            check_failures: counter!("flexc_check_failures_total", "pool" => name.clone()),
+            wait_seconds: histogram!("flexc_wait_seconds", "pool" => name.clone()),
+            connect_seconds: histogram!("flexc_connect_seconds", "pool" => name.clone()),
+            check_seconds: histogram!("flexc_check_seconds", "pool" => name),
+        }
+    }
+
+    // the start of a timing
+    pub(crate) fn start(&self) -> Option<Instant> {
+        Some(Instant::now())
+    }
+
+    // from the atomic counts, `oldest_wait` is left to `Pool::state`
+    pub(crate) fn publish(&self, status: &Status) {
+        let state = status.counts();
+        self.maxsize.set(state.maxsize);
+        self.empty.set(state.empty);
+        self.connecting.set(state.connecting);
+        self.size.set(state.size);
+        self.inuse.set(state.inuse);
+        self.idle.set(state.idle);
+        self.incheck.set(state.incheck);
+        self.wait.set(state.wait);
+    }
+
+    pub(crate) fn checkout(&self) {
+        self.checkouts.increment(1);
+    }
+
+    // the wait from the start of a checkout until a connection is taken for it, connect and check excluded
+    pub(crate) fn wait(&self, start: Option<Instant>) {
+        self.wait_seconds
+            .record(start.map(|s| s.elapsed()).unwrap_or_default());
+    }
+
+    pub(crate) fn timeout(&self) {
+        self.timeouts.increment(1);
+    }
+
+    pub(crate) fn connect(&self, elapsed: Duration, ok: bool) {
+        self.connect_seconds.record(elapsed);
+        if !ok {
+            self.connect_failures.increment(1);
+        }
+    }
+
+    pub(crate) fn check(&self, elapsed: Duration, ok: bool) {
+        self.check_seconds.record(elapsed);
+        if !ok {
+            self.check_failures.increment(1);
+        }
+    }
+}
+
+// does nothing without the metrics feature
+#[cfg(not(feature = "metrics"))]
+pub(crate) struct Metrics;
+
+#[cfg(not(feature = "metrics"))]
+impl Metrics {
+    pub(crate) fn new(_name: &str) -> Self {
+        Self
+    }
+    pub(crate) fn start(&self) -> Option<Instant> {
+        None
+    }
+    pub(crate) fn publish(&self, _status: &Status) {}
+    pub(crate) fn checkout(&self) {}
+    pub(crate) fn wait(&self, _start: Option<Instant>) {}
+    pub(crate) fn timeout(&self) {}
+    pub(crate) fn connect(&self, _elapsed: Duration, _ok: bool) {}
+    pub(crate) fn check(&self, _elapsed: Duration, _ok: bool) {}
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+/// Metadata of a pooled connection, passed to the `RecyclePolicy`.
+#[derive(Clone, Debug)]
+pub struct ConnInfo {
+    /// The index of the connection in the pool
+    pub idx: usize,
+    /// Time since the connection was established
+    pub age: Duration,
+    /// The number of times the connection has been checked out
+    pub uses: u64,
+    /// How long the connection was held by the last user
+    pub held: Duration,
+    /// Time since the last error reported by `PooledConnection::report_error`
+    pub last_error: Option<Duration>,
+}
+
+/// Decides whether a connection should be kept, consulted when it's returned to the pool
+/// and before it's checked out again.
+///
+/// Closures `Fn(&ConnInfo) -> bool` are policies too.
+pub trait RecyclePolicy: Send + Sync + 'static {
+    /// `false` => discard the connection, the pool will reconnect it at next checkout
+    fn recycle(&self, info: &ConnInfo) -> bool;
+}
+
+impl<F> RecyclePolicy for F
+where
+    F: Fn(&ConnInfo) -> bool + Send + Sync + 'static,
+{
+    fn recycle(&self, info: &ConnInfo) -> bool {
+        self(info)
+    }
+}
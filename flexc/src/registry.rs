@@ -0,0 +1,93 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::{Manager, Pool, PoolControl, State};
+
+/// Pools of any `Manager` by name, registered by `Builder::registry` or `PoolRegistry::register`.
+///
+/// The registry doesn't keep the pools alive, the dropped ones are removed.
+/// The names are unique, a pool registered by a taken name replaces the one registered before.
+#[derive(Default)]
+pub struct PoolRegistry {
+    pools: Mutex<Vec<(String, Weak<dyn PoolControl>)>>,
+}
+
+impl PoolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the registry of the process
+    pub fn global() -> Arc<PoolRegistry> {
+        static GLOBAL: Mutex<Option<Arc<PoolRegistry>>> = Mutex::new(None);
+        GLOBAL
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Default::default)
+            .clone()
+    }
+
+    /// register the pool by the name, replaces the pool of the same name in place
+    pub fn register<M: Manager>(&self, name: impl Into<String>, pool: &Pool<M>) {
+        let shared: Arc<dyn PoolControl> = pool.shared.clone();
+        self.insert(name.into(), Arc::downgrade(&shared));
+    }
+
+    pub(crate) fn insert(&self, name: String, pool: Weak<dyn PoolControl>) {
+        let mut pools = self.pools.lock().unwrap();
+        pools.retain(|(_, p)| p.strong_count() > 0);
+        match pools.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => entry.1 = pool,
+            None => pools.push((name, pool)),
+        }
+    }
+
+    /// the living pools by name in the order registered
+    pub fn pools(&self) -> Vec<(String, Arc<dyn PoolControl>)> {
+        let mut pools = self.pools.lock().unwrap();
+        pools.retain(|(_, p)| p.strong_count() > 0);
+        pools
+            .iter()
+            .filter_map(|(name, p)| p.upgrade().map(|p| (name.clone(), p)))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn PoolControl>> {
+        self.pools()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, p)| p)
+    }
+
+    pub fn states(&self) -> Vec<(String, State)> {
+        self.pools()
+            .into_iter()
+            .map(|(name, p)| (name, p.state()))
+            .collect()
+    }
+
+    /// close every pool, see `Pool::close`
+    pub fn close_all(&self) {
+        for (_, p) in self.pools() {
+            p.close();
+        }
+    }
+
+    /// serves an admin request for the registered pools, see `admin::handle`
+    #[cfg(feature = "admin")]
+    pub fn handle(&self, method: &str, path: &str) -> (u16, String) {
+        let pools = self.pools();
+        let pools = pools
+            .iter()
+            .map(|(name, p)| (name.as_str(), &**p))
+            .collect::<Vec<_>>();
+        crate::admin::handle(&pools, method, path)
+    }
+}
+
+impl fmt::Debug for PoolRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.pools().into_iter().map(|(name, _)| name);
+        f.debug_list().entries(names).finish()
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+use std::future::Future;
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_util::FutureExt;
+
+use crate::runtime::{default_runtime, BoxFuture, Runtime};
+use crate::{async_trait, Builder, Error, Manager, Pool, PooledConnection, State};
+
+/// A trait which provides in-memory resources created synchronously,
+/// such as compression contexts, large buffers and regex sets.
+pub trait ResourceManager: Send + Sync + 'static {
+    /// The resource type this manager deals with.
+    type Resource: Send + 'static;
+    /// The error type returned by `create`.
+    type Error: Send + 'static;
+
+    /// Creates a new resource.
+    fn create(&self) -> Result<Self::Resource, Self::Error>;
+
+    /// Resets the resource when it's returned to the pool.
+    fn reset(&self, _resource: &mut Self::Resource) {}
+
+    /// Determines if the resource can be reused after reset, the invalid one is dropped.
+    fn is_valid(&self, _resource: &Self::Resource) -> bool {
+        true
+    }
+}
+
+/// A pool of `ResourceManager::Resource`, checkout both synchronously and asynchronously.
+///
+/// `Builder::check` is ignored, resources are validated by `ResourceManager::is_valid` when returned.
+pub struct ResourcePool<R: ResourceManager> {
+    pool: Pool<Resources<R>>,
+}
+
+// adapts ResourceManager to Manager
+pub(crate) struct Resources<R>(R);
+
+#[async_trait]
+impl<R: ResourceManager> Manager for Resources<R> {
+    type Connection = R::Resource;
+    type Error = R::Error;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        self.0.create()
+    }
+
+    async fn check(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<R: ResourceManager> ResourcePool<R> {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    pub fn state(&self) -> State {
+        self.pool.state()
+    }
+
+    pub fn manager(&self) -> &R {
+        &self.pool.manager().0
+    }
+
+    pub fn config(&self) -> &Builder {
+        self.pool.config()
+    }
+
+    pub fn close(&self) {
+        self.pool.close()
+    }
+
+    /// get an idle resource or create one, `None` if all in use, never blocks
+    #[track_caller]
+    pub fn try_get(&self) -> Result<Option<PooledResource<R>>, Error<R::Error>> {
+        self.pool
+            .try_get_with(Location::caller(), || self.manager().create())
+            .map(|c| c.map(PooledResource::new))
+    }
+
+    /// get with default timeout, blocks the current thread
+    #[track_caller]
+    pub fn get_blocking(&self) -> Result<PooledResource<R>, Error<R::Error>> {
+        self.get_blocking_timeout(self.config().timeout)
+    }
+
+    /// get with custom timeout, blocks the current thread
+    #[track_caller]
+    pub fn get_blocking_timeout(
+        &self,
+        duration: Option<Duration>,
+    ) -> Result<PooledResource<R>, Error<R::Error>> {
+        let fut = self.pool.get_at(None, Location::caller());
+        match block_on_timeout(fut, duration) {
+            Some(res) => res.map(PooledResource::new),
+            None => Err(Error::Timeout("wait")),
+        }
+    }
+
+    /// get with default timeout
+    #[track_caller]
+    pub fn get(&self) -> impl Future<Output = Result<PooledResource<R>, Error<R::Error>>> + '_ {
+        self.get_timeout(self.config().timeout)
+    }
+
+    /// get with custom timeout, the timeout needs a runtime
+    #[track_caller]
+    pub fn get_timeout(
+        &self,
+        duration: Option<Duration>,
+    ) -> impl Future<Output = Result<PooledResource<R>, Error<R::Error>>> + '_ {
+        self.pool
+            .get_at(duration, Location::caller())
+            .map(|res| res.map(PooledResource::new))
+    }
+}
+
+impl Builder {
+    /// Consumes the builder, returning a new ResourcePool
+    pub fn build_resources<R: ResourceManager>(mut self, manager: R) -> ResourcePool<R> {
+        self.check = None;
+        if self.runtime.is_none() {
+            self.runtime = Some(default_runtime().unwrap_or_else(|| Arc::new(NoRuntime)));
+        }
+
+        ResourcePool {
+            pool: self.build_unchecked(Resources(manager)),
+        }
+    }
+}
+
+// the async timeouts never fire without runtime
+struct NoRuntime;
+
+impl Runtime for NoRuntime {
+    fn sleep(&self, _duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(futures_util::future::pending())
+    }
+    fn block_on(&self, future: Pin<Box<dyn Future<Output = ()> + '_>>) -> Result<(), &'static str> {
+        block_on_timeout(future, None);
+        Ok(())
+    }
+}
+
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// `None` if timed out
+fn block_on_timeout<F: Future>(fut: F, timeout: Option<Duration>) -> Option<F::Output> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    futures_util::pin_mut!(fut);
+
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return Some(output);
+        }
+        match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+                thread::park_timeout(deadline - now);
+            }
+            None => thread::park(),
+        }
+    }
+}
+
+/// A smart pointer wrapping a resource, reset when dropped.
+pub struct PooledResource<R: ResourceManager>(Option<PooledConnection<Resources<R>>>);
+
+impl<R: ResourceManager> PooledResource<R> {
+    fn new(conn: PooledConnection<Resources<R>>) -> Self {
+        Self(Some(conn))
+    }
+    /// Take this resource from the pool permanently.
+    pub fn take(mut self) -> R::Resource {
+        self.0.take().unwrap().take()
+    }
+}
+
+impl<R: ResourceManager> fmt::Debug for PooledResource<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let idx = self.0.as_ref().and_then(|c| c.0.as_ref()).map(|c| c.idx);
+        f.debug_struct("PooledResource").field("idx", &idx).finish()
+    }
+}
+
+impl<R: ResourceManager> std::ops::Deref for PooledResource<R> {
+    type Target = R::Resource;
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl<R: ResourceManager> std::ops::DerefMut for PooledResource<R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut().unwrap()
+    }
+}
+
+impl<R: ResourceManager> Drop for PooledResource<R> {
+    fn drop(&mut self) {
+        let conn = match self.0.as_mut() {
+            Some(c) => c,
+            None => return,
+        };
+        if let Some(p) = conn.0.as_ref().and_then(|c| c.shared.upgrade()) {
+            let manager = &p.manager.0;
+            manager.reset(conn.as_mut());
+            if !manager.is_valid(conn.as_ref()) {
+                conn.set_reconnect(true);
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures_util::future::{select, Either};
+
+/// An owned dynamically typed future.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The async runtime used by the pool: timer and blocking.
+///
+/// `TokioRuntime`, `AsyncStdRuntime` and `SmolRuntime` are built in behind the
+/// `tokio-rt`, `async-rt` and `smol-rt` features, select one by `Builder::runtime`.
+pub trait Runtime: Send + Sync + 'static {
+    /// A future completes after the duration.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// Runs the future to completion on the current thread, used by blocking checkout.
+    ///
+    /// `Err` with the reason if the runtime can't be driven from the current thread.
+    fn block_on(&self, future: Pin<Box<dyn Future<Output = ()> + '_>>) -> Result<(), &'static str>;
+}
+
+#[derive(Debug)]
+pub(crate) struct Elapsed;
+
+pub(crate) async fn timeout<F: Future>(
+    runtime: &dyn Runtime,
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    futures_util::pin_mut!(future);
+    match select(future, runtime.sleep(duration)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(Elapsed),
+    }
+}
+
+// yields to the executor once, for any runtime
+pub(crate) async fn yield_now() {
+    let mut yielded = false;
+    futures_util::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+// tokio if built in its runtime, then the first enabled of async-std, smol and tokio
+#[allow(unreachable_code)]
+pub(crate) fn default_runtime() -> Option<Arc<dyn Runtime>> {
+    #[cfg(feature = "tokio-rt")]
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Some(Arc::new(TokioRuntime::current()));
+    }
+    #[cfg(feature = "async-rt")]
+    return Some(Arc::new(AsyncStdRuntime));
+    #[cfg(feature = "smol-rt")]
+    return Some(Arc::new(SmolRuntime));
+    #[cfg(feature = "tokio-rt")]
+    return Some(Arc::new(TokioRuntime::current()));
+    None
+}
+
+/// tokio runtime
+#[cfg(feature = "tokio-rt")]
+#[derive(Clone, Debug)]
+pub struct TokioRuntime {
+    // the timers are created on it, otherwise in the runtime polling them
+    timer: Option<tokio::runtime::Handle>,
+    // drives the blocking checkout
+    blocking: Option<tokio::runtime::Handle>,
+}
+
+#[cfg(feature = "tokio-rt")]
+impl TokioRuntime {
+    /// timers and blocking checkout on the handle, so the pool can be used in other runtimes
+    /// as long as the handle's runtime is alive
+    pub fn new(handle: tokio::runtime::Handle) -> Self {
+        Self {
+            timer: Some(handle.clone()),
+            blocking: Some(handle),
+        }
+    }
+    /// timers in the runtime the future polled in, blocking checkout on the runtime of current thread
+    pub fn current() -> Self {
+        Self {
+            timer: None,
+            blocking: tokio::runtime::Handle::try_current().ok(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio-rt")]
+impl Runtime for TokioRuntime {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        match self.timer.as_ref() {
+            Some(h) => {
+                let _guard = h.enter();
+                Box::pin(tokio::time::sleep(duration))
+            }
+            // created when polled
+            None => Box::pin(async move { tokio::time::sleep(duration).await }),
+        }
+    }
+    fn block_on(&self, future: Pin<Box<dyn Future<Output = ()> + '_>>) -> Result<(), &'static str> {
+        match self.blocking.as_ref() {
+            Some(h) => {
+                h.block_on(future);
+                Ok(())
+            }
+            None => {
+                Err("no tokio runtime handle, build the pool in a runtime or by TokioRuntime::new")
+            }
+        }
+    }
+}
+
+/// async-std runtime
+#[cfg(feature = "async-rt")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "async-rt")]
+impl Runtime for AsyncStdRuntime {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async_std::task::sleep(duration))
+    }
+    fn block_on(&self, future: Pin<Box<dyn Future<Output = ()> + '_>>) -> Result<(), &'static str> {
+        async_std::task::block_on(future);
+        Ok(())
+    }
+}
+
+/// smol runtime(async-io timer and the global executor)
+#[cfg(feature = "smol-rt")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SmolRuntime;
+
+#[cfg(feature = "smol-rt")]
+impl Runtime for SmolRuntime {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            smol::Timer::after(duration).await;
+        })
+    }
+    fn block_on(&self, future: Pin<Box<dyn Future<Output = ()> + '_>>) -> Result<(), &'static str> {
+        smol::block_on(future);
+        Ok(())
+    }
+}
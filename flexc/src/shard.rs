@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_queue::ArrayQueue;
+use crossbeam_utils::CachePadded;
+
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // the shard index of the current thread before modulo
+    static THREAD: usize = THREADS.fetch_add(1, Ordering::Relaxed);
+}
+
+// idle queues sharded by thread, a thread steals from the other shards when its own is empty
+pub(crate) struct Shards<T> {
+    shards: Vec<CachePadded<ArrayQueue<T>>>,
+}
+
+impl<T> Shards<T> {
+    // the total capacity is at least `cap`, a full shard spills to the next one
+    pub(crate) fn new(shards: usize, cap: usize) -> Self {
+        let shards = shards.clamp(1, cap);
+        let per_shard = (cap + shards - 1) / shards;
+        Self {
+            shards: (0..shards)
+                .map(|_| CachePadded::new(ArrayQueue::new(per_shard)))
+                .collect(),
+        }
+    }
+
+    fn home(&self) -> usize {
+        match self.shards.len() {
+            1 => 0,
+            n => THREAD.with(|t| *t % n),
+        }
+    }
+
+    pub(crate) fn push(&self, mut item: T) -> Result<(), T> {
+        let (home, n) = (self.home(), self.shards.len());
+        for i in 0..n {
+            match self.shards[(home + i) % n].push(item) {
+                Ok(()) => return Ok(()),
+                Err(rejected) => item = rejected,
+            }
+        }
+        Err(item)
+    }
+
+    // a scan misses the item pushed to a shard already scanned, so it scans twice,
+    // `None` is still a miss rather than proof of empty, callers retry
+    pub(crate) fn pop(&self) -> Option<T> {
+        let (home, n) = (self.home(), self.shards.len());
+        (0..2 * n).find_map(|i| self.shards[(home + i) % n].pop())
+    }
+}
@@ -1,60 +1,212 @@
+use std::collections::BTreeMap;
 use std::sync::atomic::*;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crossbeam_utils::CachePadded;
+use event_listener::{Event, EventListener};
 
 pub(crate) const STATUS_EMPTY: u8 = 0;
 pub(crate) const STATUS_INCHECK: u8 = 1;
 pub(crate) const STATUS_INUSE: u8 = 2;
 pub(crate) const STATUS_IDLE: u8 = 3;
+pub(crate) const STATUS_CONNECTING: u8 = 4;
+// the number of slots in each non-empty status, packed in 16 bits per status so that
+// a transition updates them all at once and a snapshot is never torn
+const COUNT_BITS: u8 = 16;
+const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+const MAX_PACKED: usize = COUNT_MASK as usize;
+
 #[derive(Clone, Debug)]
-pub(crate) struct Status(pub(crate) Arc<Vec<AtomicU8>>);
+pub(crate) struct Status(pub(crate) Arc<Slots>);
+
+#[derive(Debug)]
+pub(crate) struct Slots {
+    // padded to keep the slots touched by different threads off the same cache line
+    slots: Vec<CachePadded<AtomicU8>>,
+    counts: CachePadded<Counts>,
+    // the tasks waiting for a permit by ticket, the first is the oldest
+    waiters: Mutex<BTreeMap<u64, Instant>>,
+    // the len of waiters, read without locking
+    waiting: AtomicU32,
+    ticket: AtomicU64,
+    // notified when the state changes
+    event: Event,
+}
+
+// the empty ones are the rest of maxsize
+fn count_of(status: u8) -> u64 {
+    match status {
+        STATUS_EMPTY => 0,
+        status => 1 << ((status - 1) * COUNT_BITS),
+    }
+}
+
+// the counts of the non-empty statuses, indexed by status - 1
+#[derive(Debug)]
+enum Counts {
+    // at most MAX_PACKED slots
+    Packed(AtomicU64),
+    // too many slots to pack, updated and read under the lock
+    Wide(Mutex<[u64; 4]>),
+}
+
+impl Counts {
+    fn new(maxsize: usize) -> Self {
+        if maxsize <= MAX_PACKED {
+            Self::Packed(AtomicU64::new(0))
+        } else {
+            Self::Wide(Mutex::default())
+        }
+    }
+
+    fn transit(&self, old: u8, new: u8) {
+        match self {
+            Self::Packed(counts) => {
+                // the old count is at least 1, the subtraction never borrows from the next count
+                let delta = count_of(new).wrapping_sub(count_of(old));
+                counts.fetch_add(delta, Ordering::SeqCst);
+            }
+            Self::Wide(counts) => {
+                let mut counts = counts.lock().unwrap();
+                if old != STATUS_EMPTY {
+                    counts[old as usize - 1] -= 1;
+                }
+                if new != STATUS_EMPTY {
+                    counts[new as usize - 1] += 1;
+                }
+            }
+        }
+    }
+
+    fn load(&self) -> [u64; 4] {
+        match self {
+            Self::Packed(counts) => {
+                let counts = counts.load(Ordering::SeqCst);
+                [0, 1, 2, 3].map(|i| (counts >> (i * COUNT_BITS)) & COUNT_MASK)
+            }
+            Self::Wide(counts) => *counts.lock().unwrap(),
+        }
+    }
+}
+
+// registered while waiting for a permit
+pub(crate) struct Waiter<'a> {
+    slots: &'a Slots,
+    ticket: u64,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.slots.waiters.lock().unwrap().remove(&self.ticket);
+        self.slots.waiting.fetch_sub(1, Ordering::SeqCst);
+        self.slots.event.notify(usize::MAX);
+    }
+}
 
 impl Status {
     pub fn new(maxsize: usize) -> Self {
-        let this = (0..maxsize)
-            .into_iter()
-            .map(|_| AtomicU8::new(STATUS_EMPTY))
+        let slots = (0..maxsize)
+            .map(|_| CachePadded::new(AtomicU8::new(STATUS_EMPTY)))
             .collect::<Vec<_>>();
-        Self(Arc::new(this))
+        Self(Arc::new(Slots {
+            slots,
+            counts: CachePadded::new(Counts::new(maxsize)),
+            waiters: Mutex::default(),
+            waiting: AtomicU32::new(0),
+            ticket: AtomicU64::new(0),
+            event: Event::new(),
+        }))
+    }
+
+    fn set(&self, idx: usize, status: u8) {
+        let old = self.0.slots[idx].swap(status, Ordering::SeqCst);
+        if old != status {
+            self.0.counts.transit(old, status);
+            self.notify();
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> u8 {
+        self.0.slots[idx].load(Ordering::Relaxed)
     }
 
     pub fn set_empty(&self, idx: usize) {
-        self.0[idx].store(STATUS_EMPTY, Ordering::SeqCst)
+        self.set(idx, STATUS_EMPTY)
     }
 
     pub fn set_incheck(&self, idx: usize) {
-        self.0[idx].store(STATUS_INCHECK, Ordering::SeqCst)
+        self.set(idx, STATUS_INCHECK)
     }
 
     pub fn is_incheck(&self, idx: usize) -> bool {
-        self.0[idx].load(Ordering::Relaxed) == STATUS_INCHECK
+        self.get(idx) == STATUS_INCHECK
+    }
+
+    pub fn set_connecting(&self, idx: usize) {
+        self.set(idx, STATUS_CONNECTING)
     }
 
     pub fn set_inuse(&self, idx: usize) {
-        self.0[idx].store(STATUS_INUSE, Ordering::SeqCst)
+        self.set(idx, STATUS_INUSE)
     }
 
     pub fn set_idle(&self, idx: usize) {
-        self.0[idx].store(STATUS_IDLE, Ordering::SeqCst)
+        self.set(idx, STATUS_IDLE)
     }
 
-    pub fn state(&self) -> State {
-        let mut state = State::default();
-        state.maxsize = self.0.len() as _;
-
-        for (idx, s) in self.0.as_slice().iter().enumerate() {
-            let s = s.load(Ordering::Relaxed);
-            match s {
-                STATUS_EMPTY => state.empty += 1,
-                STATUS_INCHECK => state.incheck += 1,
-                STATUS_INUSE => state.inuse += 1,
-                STATUS_IDLE => state.idle += 1,
-                invalid => unreachable!("conn-{} invalid status: {}", idx, invalid),
-            }
+    pub fn wait(&self) -> Waiter<'_> {
+        let ticket = self.0.ticket.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .waiters
+            .lock()
+            .unwrap()
+            .insert(ticket, Instant::now());
+        self.0.waiting.fetch_add(1, Ordering::SeqCst);
+        self.notify();
+        Waiter {
+            slots: &self.0,
+            ticket,
         }
-        state.size = state.inuse + state.idle + state.incheck;
-        state.maxsize = state.size + state.empty;
-        state.wait = Arc::weak_count(&self.0) as _;
+    }
+
+    pub fn notify(&self) {
+        self.0.event.notify(usize::MAX);
+    }
 
+    pub fn listen(&self) -> EventListener {
+        self.0.event.listen()
+    }
+
+    pub fn state(&self) -> State {
+        let mut state = self.counts();
+        state.oldest_wait = self
+            .0
+            .waiters
+            .lock()
+            .unwrap()
+            .values()
+            .next()
+            .map(Instant::elapsed);
+        state
+    }
+
+    // the state without `oldest_wait`, never locks the waiters
+    pub fn counts(&self) -> State {
+        let counts = self.0.counts.load();
+        let count = |status: u8| counts[status as usize - 1] as u32;
+
+        let mut state = State {
+            maxsize: self.0.slots.len() as _,
+            connecting: count(STATUS_CONNECTING),
+            incheck: count(STATUS_INCHECK),
+            inuse: count(STATUS_INUSE),
+            idle: count(STATUS_IDLE),
+            ..Default::default()
+        };
+        state.size = state.inuse + state.idle + state.incheck;
+        state.empty = state.maxsize - state.size - state.connecting;
+        state.wait = self.0.waiting.load(Ordering::SeqCst);
         state
     }
 }
@@ -67,6 +219,8 @@ pub struct State {
     pub maxsize: u32,
     /// Number of unconnected
     pub empty: u32,
+    /// The number of connections currently connecting
+    pub connecting: u32,
 
     // Pool Status
     /// The number of established connections both in use, in check and idle
@@ -78,6 +232,52 @@ pub struct State {
     /// The number of connections currently in check
     pub incheck: u32,
 
-    /// The total number of connections waited for
+    /// The number of tasks waiting for a connection to be returned,
+    /// the tasks connecting or checking are counted by `connecting` and `incheck`
     pub wait: u32,
+    /// How long the oldest task has been waiting
+    pub oldest_wait: Option<Duration>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The status of a slot in a `Pool`.
+pub enum SlotStatus {
+    Empty,
+    Connecting,
+    Incheck,
+    Inuse,
+    Idle,
+}
+
+impl SlotStatus {
+    pub(crate) fn from_u8(status: u8) -> Self {
+        match status {
+            STATUS_EMPTY => Self::Empty,
+            STATUS_CONNECTING => Self::Connecting,
+            STATUS_INCHECK => Self::Incheck,
+            STATUS_INUSE => Self::Inuse,
+            STATUS_IDLE => Self::Idle,
+            invalid => unreachable!("invalid status: {}", invalid),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+/// A snapshot of a slot in a `Pool`, see `Pool::slots`.
+pub struct Slot {
+    /// The index of the slot in the pool
+    pub idx: usize,
+    pub status: SlotStatus,
+    /// Time since the connection was established, `None` if empty
+    pub age: Option<Duration>,
+    /// Time since the connection was last checked
+    pub since_check: Option<Duration>,
+    /// Time since the connection was last checked out
+    pub since_checkout: Option<Duration>,
+    /// How long the current holder has held the connection
+    pub held: Option<Duration>,
+    /// The number of times the connection has been checked out
+    pub uses: u64,
 }
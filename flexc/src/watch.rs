@@ -0,0 +1,68 @@
+use std::sync::Weak;
+use std::time::Duration;
+
+use futures_util::stream::{self, Stream};
+
+use crate::compat::SemaphoreWrap;
+use crate::status::Status;
+use crate::{Manager, SharedPool, State};
+
+// yields the state when the counts change, ends when the pool closed or dropped
+pub(crate) struct Watch<M: Manager> {
+    shared: Weak<SharedPool<M>>,
+    status: Status,
+    coalesce: Option<Duration>,
+    last: Option<State>,
+}
+
+impl<M: Manager> Watch<M> {
+    pub(crate) fn new(
+        shared: Weak<SharedPool<M>>,
+        status: Status,
+        coalesce: Option<Duration>,
+    ) -> Self {
+        Self {
+            shared,
+            status,
+            coalesce,
+            last: None,
+        }
+    }
+
+    pub(crate) fn into_stream(self) -> impl Stream<Item = State> + Send + 'static {
+        stream::unfold(self, |mut watch| async move {
+            let state = watch.next().await?;
+            Some((state, watch))
+        })
+    }
+
+    async fn next(&mut self) -> Option<State> {
+        loop {
+            // listen before reading, no change is missed in between
+            let listener = self.status.listen();
+            let runtime = {
+                let shared = self.shared.upgrade()?;
+                if SemaphoreWrap::is_closed(&*shared.semaphore) {
+                    return None;
+                }
+                shared.runtime.clone()
+            };
+
+            let state = self.status.state();
+            // the age of the oldest waiter changes all the time, it's not a change itself
+            let counts = |s: &State| State {
+                oldest_wait: s.oldest_wait.map(|_| Duration::from_secs(0)),
+                ..s.clone()
+            };
+            if self.last.as_ref().map(counts) != Some(counts(&state)) {
+                self.last = Some(state.clone());
+                return Some(state);
+            }
+
+            listener.await;
+            if let Some(coalesce) = self.coalesce {
+                runtime.sleep(coalesce).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    time::*,
+};
+
+// cargo test --manifest-path flexc/Cargo.toml --release --test bench -- --nocapture
+use flexc::{async_trait, Manager};
+
+#[cfg(feature = "tokio-rt")]
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(fut)
+}
+
+#[cfg(all(feature = "async-rt", not(feature = "tokio-rt")))]
+use async_std::task::block_on;
+
+#[cfg(all(
+    feature = "smol-rt",
+    not(feature = "tokio-rt"),
+    not(feature = "async-rt")
+))]
+use smol::block_on;
+
+type Pool = flexc::Pool<NopManager>;
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+struct NopManager;
+
+#[async_trait]
+impl Manager for NopManager {
+    type Connection = usize;
+    type Error = ();
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(0)
+    }
+    async fn check(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+// allocations and time per checkout
+async fn bench(pool: &Pool) -> (usize, Duration) {
+    const ROUNDS: usize = 10_000;
+
+    drop(pool.get().await.unwrap());
+    let allocs = ALLOCS.with(|a| a.get());
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        drop(pool.get().await.unwrap());
+    }
+    let elapsed = start.elapsed() / ROUNDS as u32;
+    (ALLOCS.with(|a| a.get()) - allocs, elapsed)
+}
+
+#[test]
+fn bench_checkout() {
+    // check on every checkout
+    let pool = Pool::builder()
+        .maxsize(1)
+        .timeout(Some(Duration::from_secs(1)))
+        .build_unchecked(NopManager);
+    let (slow_allocs, slow) = block_on(bench(&pool));
+
+    let pool = Pool::builder()
+        .maxsize(1)
+        .timeout(Some(Duration::from_secs(1)))
+        .check(Some(Duration::from_secs(60)))
+        .build_unchecked(NopManager);
+    let (fast_allocs, fast) = block_on(bench(&pool));
+
+    println!(
+        "checked: {} allocs, {:?}/get; fast path: {} allocs, {:?}/get",
+        slow_allocs, slow, fast_allocs, fast
+    );
+    assert_eq!(fast_allocs, 0);
+    assert!(slow_allocs > fast_allocs);
+}
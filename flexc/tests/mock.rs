@@ -3,22 +3,41 @@ use std::{
     time::*,
 };
 
-use tokio::time::timeout;
 // cargo test --manifest-path flexc/Cargo.toml --release -- --test-threads=1 --nocapture
-#[cfg(any(feature = "tokio-rt"))]
+#[cfg(feature = "tokio-rt")]
 use tokio::{
-    task::{spawn, yield_now},
+    task::{spawn, spawn_blocking, yield_now},
     test as atest,
-    time::sleep,
+    time::{sleep, timeout},
 };
 
 // cargo test --no-default-features --features async-rt  --manifest-path flexc/Cargo.toml --release -- --test-threads=1 --nocapture
-#[cfg(any(feature = "async-rt"))]
+// cargo test --no-default-features --features smol-rt  --manifest-path flexc/Cargo.toml --release -- --test-threads=1 --nocapture
+// the pool runs on SmolRuntime, the tests are driven by async-std which shares the async-io reactor
+#[cfg(all(
+    any(feature = "async-rt", feature = "smol-rt"),
+    not(feature = "tokio-rt")
+))]
 use async_std::{
-    task::{sleep, spawn, yield_now},
+    future::timeout,
+    task::{sleep, spawn, spawn_blocking, yield_now},
     test as atest,
 };
 
+// awaits a spawned task, resuming its panic
+#[cfg(feature = "tokio-rt")]
+async fn join<T>(task: tokio::task::JoinHandle<T>) -> T {
+    task.await.unwrap()
+}
+
+#[cfg(all(
+    any(feature = "async-rt", feature = "smol-rt"),
+    not(feature = "tokio-rt")
+))]
+async fn join<T>(task: async_std::task::JoinHandle<T>) -> T {
+    task.await
+}
+
 use flexc::{async_trait, Manager};
 type Pool = flexc::Pool<MockManager>;
 
@@ -113,6 +132,51 @@ impl Manager for MockManager {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+struct LocalMockManager {
+    connects: std::rc::Rc<std::cell::Cell<usize>>,
+}
+
+#[async_trait(?Send)]
+impl flexc::LocalManager for LocalMockManager {
+    type Connection = std::rc::Rc<std::cell::Cell<usize>>;
+    type Error = ();
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        sleep(Duration::from_millis(2)).await;
+        self.connects.set(self.connects.get() + 1);
+        Ok(Default::default())
+    }
+
+    async fn check(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        yield_now().await;
+        Ok(())
+    }
+}
+
+// connect and check wait for the gates
+#[derive(Debug, Clone, Default)]
+struct GateManager {
+    connect: Arc<futures::lock::Mutex<()>>,
+    check: Arc<futures::lock::Mutex<()>>,
+}
+
+#[async_trait]
+impl Manager for GateManager {
+    type Connection = ();
+    type Error = ();
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        drop(self.connect.lock().await);
+        Ok(())
+    }
+
+    async fn check(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        drop(self.check.lock().await);
+        Ok(())
+    }
+}
+
 #[atest]
 async fn test_basic() {
     let manager = MockManager::new();
@@ -166,6 +230,7 @@ async fn test_basic() {
 }
 
 #[atest]
+#[allow(clippy::unnecessary_literal_unwrap)]
 async fn test_reconnect_for_use() {
     let manager = MockManager::new();
     let duration = Some(Duration::from_secs(1));
@@ -214,6 +279,1110 @@ async fn test_reconnect_for_use() {
     assert_eq!(status.empty, 0);
 }
 
+#[atest]
+async fn test_recycle_policy() {
+    let manager = MockManager::new();
+    let pool = Pool::builder()
+        .maxsize(1)
+        .check(None)
+        .recycle_policy(|info: &flexc::ConnInfo| info.uses < 2 && info.last_error.is_none())
+        .build_unchecked(manager.clone());
+
+    drop(pool.get().await.unwrap());
+    assert_eq!(pool.state().idle, 1);
+    assert_eq!(manager.size(), 1);
+
+    // discard after 2 uses
+    let con = pool.get().await.unwrap();
+    assert_eq!(con.count, 0);
+    drop(con);
+    let status = pool.state();
+    assert_eq!(status.idle, 0);
+    assert_eq!(status.empty, 1);
+    assert_eq!(manager.size(), 0);
+
+    // discard after error reported
+    let mut con = pool.get().await.unwrap();
+    con.report_error();
+    drop(con);
+    assert_eq!(pool.state().empty, 1);
+    assert_eq!(manager.size(), 0);
+}
+
+#[atest]
+async fn test_recycle_policy_once() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls2 = calls.clone();
+    let pool = Pool::builder()
+        .maxsize(1)
+        .check(Some(Duration::from_secs(0)))
+        .recycle_policy(move |_: &flexc::ConnInfo| {
+            calls2.fetch_add(1, Ordering::Relaxed);
+            true
+        })
+        .build_unchecked(MockManager::new());
+
+    // the empty slot is not consulted at checkout
+    drop(pool.get().await.unwrap());
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    // once at checkout and once on return
+    drop(pool.get().await.unwrap());
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+}
+
+#[atest]
+async fn test_recycle_policy_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let panics = Arc::new(AtomicBool::new(false));
+    let panics2 = panics.clone();
+    let pool = Pool::builder()
+        .maxsize(1)
+        .check(None)
+        .timeout(Some(Duration::from_millis(100)))
+        .recycle_policy(move |_: &flexc::ConnInfo| {
+            assert!(!panics2.load(Ordering::Relaxed), "recycle policy");
+            true
+        })
+        .build_unchecked(MockManager::new());
+
+    // on return, the connection is discarded and the slot given back before the panic resumes
+    let con = pool.get().await.unwrap();
+    panics.store(true, Ordering::Relaxed);
+    assert!(catch_unwind(AssertUnwindSafe(|| drop(con))).is_err());
+    let status = pool.state();
+    assert_eq!(status.empty, 1);
+    assert_eq!(status.inuse, 0);
+
+    // at checkout, the slot is given back to the pool(the connection discarded on panic)
+    panics.store(false, Ordering::Relaxed);
+    drop(pool.get().await.unwrap());
+    panics.store(true, Ordering::Relaxed);
+    assert!(catch_unwind(AssertUnwindSafe(|| pool.try_get_now())).is_err());
+    let status = pool.state();
+    assert_eq!(status.empty, 1);
+    assert_eq!(status.inuse, 0);
+
+    panics.store(false, Ordering::Relaxed);
+    let _con = pool.get().await.unwrap();
+    assert!(pool.try_get_now().unwrap().is_none());
+    assert!(pool.get().await.unwrap_err().is_timeout());
+}
+
+#[atest]
+async fn test_leak_detection() {
+    let leaked = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let leaked2 = leaked.clone();
+    let pool = Pool::builder()
+        .maxsize(1)
+        .timeout(Some(Duration::from_millis(50)))
+        .leak_detection(Some(Duration::from_millis(10)))
+        .leak_backtrace(true)
+        .on_leak(move |held: &flexc::HeldConnection| leaked2.lock().unwrap().push(held.clone()))
+        .build_unchecked(MockManager::new());
+
+    let line = line!() + 1;
+    let con = pool.get().await.unwrap();
+    let held = pool.held_connections();
+    assert_eq!(held.len(), 1);
+    assert_eq!(held[0].caller.file(), file!());
+    assert_eq!(held[0].caller.line(), line);
+
+    assert!(pool.try_get().await.unwrap().is_none());
+    assert!(leaked.lock().unwrap().is_empty());
+
+    sleep(Duration::from_millis(20)).await;
+    assert!(pool.get().await.unwrap_err().is_timeout());
+    assert!(pool.try_get().await.unwrap().is_none());
+    {
+        let leaked = leaked.lock().unwrap();
+        assert_eq!(leaked.len(), 1);
+        assert_eq!(leaked[0].caller.line(), line);
+        assert!(leaked[0].held >= Duration::from_millis(10));
+        assert!(leaked[0].backtrace.is_some());
+    }
+
+    drop(con);
+    assert!(pool.held_connections().is_empty());
+}
+
+#[atest]
+async fn test_leak_detection_not_exhausted() {
+    let leaked = Arc::new(AtomicUsize::new(0));
+    let leaked2 = leaked.clone();
+    let pool = Pool::builder()
+        .maxsize(2)
+        .check(None)
+        .leak_detection(Some(Duration::from_millis(10)))
+        .on_leak(move |_: &flexc::HeldConnection| {
+            leaked2.fetch_add(1, Ordering::SeqCst);
+        })
+        .build_unchecked(MockManager::new());
+
+    let _con = pool.get().await.unwrap();
+    sleep(Duration::from_millis(20)).await;
+    // reported by the checkouts and returns of the other connection
+    drop(pool.get().await.unwrap());
+    assert_eq!(leaked.load(Ordering::SeqCst), 1);
+    drop(pool.get().await.unwrap());
+    assert_eq!(leaked.load(Ordering::SeqCst), 1);
+}
+
+#[atest]
+async fn test_max_hold_time() {
+    let manager = MockManager::new();
+    let pool = Pool::builder()
+        .maxsize(1)
+        .timeout(Some(Duration::from_millis(500)))
+        .max_hold_time(Some(Duration::from_millis(50)))
+        .build_unchecked(manager.clone());
+
+    let stale = pool.get().await.unwrap();
+    assert!(!stale.revoked());
+    assert!(pool.try_get().await.unwrap().is_none());
+
+    // the waiter gets a new connection after the stale one revoked
+    let now = Instant::now();
+    let con = pool.get().await.unwrap();
+    assert!(now.elapsed() >= Duration::from_millis(40));
+    assert!(stale.revoked());
+    assert!(!con.revoked());
+    assert_eq!(manager.size(), 2);
+
+    drop(stale);
+    let status = pool.state();
+    assert_eq!(status.inuse, 1);
+    assert_eq!(status.idle, 0);
+    assert_eq!(manager.size(), 1);
+
+    drop(con);
+    assert_eq!(pool.state().idle, 1);
+    assert_eq!(manager.size(), 1);
+}
+
+#[atest]
+async fn test_state_stages() {
+    let manager = GateManager::default();
+    let pool = Arc::new(
+        flexc::Pool::<GateManager>::builder()
+            .maxsize(1)
+            .timeout(None)
+            .build_unchecked(manager.clone()),
+    );
+    let connect = manager.connect.lock().await;
+    let check = manager.check.lock().await;
+
+    for _ in 0..2 {
+        let p = pool.clone();
+        spawn(async move { drop(p.get().await.unwrap()) });
+    }
+    sleep(Duration::from_millis(20)).await;
+    let status = pool.state();
+    assert_eq!(status.connecting, 1);
+    assert_eq!(status.empty, 0);
+    assert_eq!(status.size, 0);
+    assert_eq!(status.wait, 1);
+    assert!(status.oldest_wait.unwrap() >= Duration::from_millis(10));
+
+    drop(connect);
+    sleep(Duration::from_millis(20)).await;
+    let status = pool.state();
+    assert_eq!(status.connecting, 0);
+    assert_eq!(status.incheck, 1);
+    assert_eq!(status.wait, 1);
+
+    drop(check);
+    sleep(Duration::from_millis(20)).await;
+    let status = pool.state();
+    assert_eq!(status.idle, 1);
+    assert_eq!(status.wait, 0);
+    assert_eq!(status.oldest_wait, None);
+}
+
+#[atest]
+async fn test_state_wide() {
+    const MAXSIZE: usize = 1 << 16;
+    let pool = Pool::builder()
+        .maxsize(MAXSIZE)
+        .check(None)
+        .build_unchecked(MockManager::new());
+    let cons = [pool.get().await.unwrap(), pool.get().await.unwrap()];
+    let status = pool.state();
+    assert_eq!(status.maxsize, MAXSIZE as u32);
+    assert_eq!(status.inuse, 2);
+    assert_eq!(status.empty, MAXSIZE as u32 - 2);
+
+    drop(cons);
+    let status = pool.state();
+    assert_eq!(status.idle, 2);
+    assert_eq!(status.inuse, 0);
+}
+
+#[atest]
+async fn test_watch() {
+    use futures::StreamExt;
+
+    let pool = Pool::builder()
+        .maxsize(1)
+        .check(None)
+        .build_unchecked(MockManager::new());
+    let mut states = Box::pin(pool.watch(Some(Duration::from_millis(1))));
+    assert_eq!(states.next().await.unwrap().empty, 1);
+
+    let con = pool.get().await.unwrap();
+    let next = timeout(Duration::from_millis(100), states.next());
+    assert_eq!(next.await.unwrap().unwrap().inuse, 1);
+
+    drop(con);
+    let next = timeout(Duration::from_millis(100), states.next());
+    assert_eq!(next.await.unwrap().unwrap().idle, 1);
+
+    pool.close();
+    assert!(states.next().await.is_none());
+}
+
+#[atest]
+async fn test_pause() {
+    let pool = Arc::new(
+        Pool::builder()
+            .maxsize(1)
+            .check(None)
+            .timeout(Some(Duration::from_millis(20)))
+            .build(MockManager::new())
+            .await
+            .unwrap(),
+    );
+
+    pool.pause();
+    assert!(pool.try_get().await.unwrap().is_none());
+    assert!(pool.get().await.unwrap_err().is_timeout());
+
+    let p = pool.clone();
+    let task = spawn(async move { p.get_timeout(None).await.is_ok() });
+    sleep(Duration::from_millis(10)).await;
+    assert_eq!(pool.state().wait, 1);
+    pool.resume();
+    assert!(join(task).await);
+
+    pool.clear_idle();
+    assert_eq!(pool.state().empty, 1);
+
+    // every shard cleared
+    let manager = MockManager::new();
+    let pool = Pool::builder()
+        .maxsize(4)
+        .shards(2)
+        .check(None)
+        .build(manager.clone())
+        .await
+        .unwrap();
+    pool.clear_idle();
+    assert_eq!(pool.state().empty, 4);
+    assert_eq!(manager.size(), 0);
+}
+
+#[cfg(feature = "admin")]
+#[atest]
+async fn test_admin() {
+    use flexc::{admin, PoolControl};
+
+    let pool = Pool::builder()
+        .maxsize(2)
+        .build(MockManager::new())
+        .await
+        .unwrap();
+    let pools: &[(&str, &dyn PoolControl)] = &[("mock", &pool)];
+
+    let (code, body) = admin::handle(pools, "GET", "/pools");
+    assert_eq!(code, 200);
+    let all: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(all["mock"]["state"]["idle"], 2);
+    assert_eq!(all["mock"]["health"], "Healthy");
+    assert_eq!(all["mock"]["recent"]["connected"], 2);
+    assert_eq!(all["mock"]["recent"]["failed"], 0);
+
+    assert_eq!(admin::handle(pools, "POST", "/pools/mock/pause").0, 200);
+    assert!(pool.is_paused());
+    assert_eq!(
+        admin::handle(pools, "POST", "/pools/mock/clear_idle").0,
+        200
+    );
+    assert_eq!(pool.state().empty, 2);
+
+    let (code, body) = admin::handle(pools, "GET", "/pools/mock");
+    assert_eq!(code, 200);
+    let detail: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(detail["paused"], true);
+    assert_eq!(detail["slots"].as_array().unwrap().len(), 2);
+    assert_eq!(detail["config"]["maxsize"], 2);
+
+    assert_eq!(admin::handle(pools, "GET", "/pools/other").0, 404);
+    assert_eq!(admin::handle(pools, "DELETE", "/pools/mock").0, 405);
+}
+
+#[atest]
+async fn test_registry() {
+    let registry = Arc::new(flexc::PoolRegistry::new());
+    let pool = Pool::builder()
+        .name("mock")
+        .registry(registry.clone())
+        .build(MockManager::new())
+        .await
+        .unwrap();
+    let gate = flexc::Pool::<GateManager>::builder()
+        .name("gate")
+        .registry(registry.clone())
+        .build_unchecked(GateManager::default());
+    let resources = flexc::ResourcePool::<BufferManager>::builder()
+        .maxsize(3)
+        .name("buffer")
+        .registry(registry.clone())
+        .build_resources(BufferManager);
+
+    let states = registry.states();
+    let names = states.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["mock", "gate", "buffer"]);
+    assert_eq!(states[0].1.idle, 20);
+    assert_eq!(states[2].1.maxsize, 3);
+
+    drop(gate);
+    assert_eq!(registry.pools().len(), 2);
+    assert!(registry.get("gate").is_none());
+
+    // replaced by the same name
+    let other = Pool::builder()
+        .maxsize(1)
+        .build_unchecked(MockManager::new());
+    registry.register("mock", &other);
+    let names = registry
+        .pools()
+        .into_iter()
+        .map(|(n, _)| n)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["mock", "buffer"]);
+    assert_eq!(registry.get("mock").unwrap().state().maxsize, 1);
+    registry.register("mock", &pool);
+
+    registry.close_all();
+    assert!(pool.is_closed());
+    assert!(registry.get("buffer").unwrap().is_closed());
+    drop(resources);
+}
+
+#[atest]
+async fn test_health() {
+    use flexc::Health;
+
+    let pool = Arc::new(
+        Pool::builder()
+            .maxsize(1)
+            .check(None)
+            .timeout(Some(Duration::from_millis(20)))
+            .build_unchecked(MockManager::new()),
+    );
+    assert_eq!(pool.health(), Health::Healthy);
+    let ready = pool.wait_ready(1, Some(Duration::from_millis(10))).await;
+    assert!(ready.unwrap_err().is_timeout());
+
+    let p = pool.clone();
+    spawn(async move { drop(p.get().await.unwrap()) });
+    pool.wait_ready(2, None).await.unwrap();
+    assert_eq!(pool.state().size, 1);
+
+    let con = pool.get().await.unwrap();
+    assert!(pool.get().await.unwrap_err().is_timeout());
+    assert_eq!(pool.health(), Health::Degraded);
+    drop(con);
+
+    pool.close();
+    assert_eq!(pool.health(), Health::Unhealthy);
+    assert!(pool.wait_ready(1, None).await.unwrap_err().is_closed());
+}
+
+#[atest]
+async fn test_try_get_now() {
+    let pool = Pool::builder()
+        .maxsize(1)
+        .check(Some(Duration::from_millis(50)))
+        .build_unchecked(MockManager::new());
+
+    assert!(pool.try_get_now().unwrap().is_none());
+    assert_eq!(pool.state().empty, 1);
+
+    drop(pool.get().await.unwrap());
+    let con = pool.try_get_now().unwrap().unwrap();
+    assert!(pool.try_get_now().unwrap().is_none());
+    drop(con);
+
+    // due to check
+    sleep(Duration::from_millis(60)).await;
+    assert!(pool.try_get_now().unwrap().is_none());
+    assert_eq!(pool.state().idle, 1);
+
+    pool.close();
+    assert!(pool.try_get_now().unwrap_err().is_closed());
+}
+
+#[atest]
+async fn test_try_get_now_untouched() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls2 = calls.clone();
+    let pool = Pool::builder()
+        .maxsize(4)
+        .shards(2)
+        .check(None)
+        .recycle_policy(move |_: &flexc::ConnInfo| {
+            calls2.fetch_add(1, Ordering::Relaxed);
+            true
+        })
+        .build_unchecked(MockManager::new());
+
+    // one idle among the empty ones
+    drop(pool.get().await.unwrap());
+    calls.store(0, Ordering::Relaxed);
+    let mut hits = 0;
+    for _ in 0..8 {
+        if let Some(con) = pool.try_get_now().unwrap() {
+            hits += 1;
+            drop(con);
+        }
+    }
+    assert!(hits > 0);
+    // once at checkout and once on return, never for the empty ones given back
+    assert_eq!(calls.load(Ordering::Relaxed), 2 * hits);
+    let status = pool.state();
+    assert_eq!(status.idle, 1);
+    assert_eq!(status.empty, 3);
+}
+
+#[atest]
+async fn test_with_conn() {
+    let pool = Pool::builder()
+        .maxsize(1)
+        .build_unchecked(MockManager::new());
+
+    let count = pool
+        .with_conn(None, |conn| {
+            Box::pin(async move {
+                conn.count += 1;
+                conn.count
+            })
+        })
+        .await;
+    assert_eq!(count.unwrap(), 1);
+    assert_eq!(pool.state().idle, 1);
+
+    let slow = Duration::from_millis(50);
+    let res = pool
+        .with_conn(Some(Duration::from_millis(20)), |_| Box::pin(sleep(slow)))
+        .await;
+    assert!(res.unwrap_err().is_timeout());
+    assert_eq!(pool.state().empty, 1);
+
+    // the future dropped in the operation
+    drop(pool.get().await.unwrap());
+    let res = timeout(
+        Duration::from_millis(20),
+        pool.with_conn(None, |_| Box::pin(sleep(slow))),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(pool.state().empty, 1);
+}
+
+#[atest]
+async fn test_discard_on_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    for discard in [true, false] {
+        let pool = Pool::builder()
+            .maxsize(1)
+            .discard_on_panic(discard)
+            .build_unchecked(MockManager::new());
+
+        let con = pool.get().await.unwrap();
+        let res = catch_unwind(AssertUnwindSafe(move || {
+            let _con = con;
+            panic!("mid-operation");
+        }));
+        assert!(res.is_err());
+
+        let state = pool.state();
+        assert_eq!(state.empty, discard as u32);
+        assert_eq!(state.idle, !discard as u32);
+    }
+}
+
+#[atest]
+async fn test_deadline_and_cancel() {
+    let pool = Pool::builder()
+        .maxsize(1)
+        .timeout(Some(Duration::from_secs(1)))
+        .build_unchecked(MockManager::new());
+
+    let con = pool.get().await.unwrap();
+    let deadline = Instant::now() + Duration::from_millis(20);
+    assert!(pool.get_until(deadline).await.unwrap_err().is_timeout());
+    assert!(Instant::now() >= deadline);
+
+    let cancel = sleep(Duration::from_millis(20));
+    assert!(pool
+        .get_with_cancel(cancel)
+        .await
+        .unwrap_err()
+        .is_cancelled());
+    drop(con);
+
+    let con = pool
+        .get_with_cancel(futures::future::pending())
+        .await
+        .unwrap();
+    drop(con);
+    let cancelled = pool.get_with_cancel(async {}).await;
+    assert!(cancelled.unwrap_err().is_cancelled());
+    assert_eq!(pool.state().idle, 1);
+}
+
+#[atest]
+async fn test_deadline_with_cancel() {
+    let pool = Pool::builder()
+        .maxsize(1)
+        .build_unchecked(MockManager::new());
+    let _con = pool.get().await.unwrap();
+
+    // the time left is taken when polled, not when created
+    let deadline = Instant::now() + Duration::from_millis(60);
+    let get = pool.get_until(deadline);
+    sleep(Duration::from_millis(40)).await;
+    assert!(get.await.unwrap_err().is_timeout());
+    assert!(Instant::now() < deadline + Duration::from_millis(30));
+
+    let deadline = Instant::now() + Duration::from_millis(20);
+    let cancel = sleep(Duration::from_secs(1));
+    let res = pool.get_until_with_cancel(deadline, cancel).await;
+    assert!(res.unwrap_err().is_timeout());
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let cancel = sleep(Duration::from_millis(20));
+    let res = pool.get_until_with_cancel(deadline, cancel).await;
+    assert!(res.unwrap_err().is_cancelled());
+}
+
+#[cfg(feature = "metrics")]
+#[atest]
+async fn test_metrics() {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let pool = metrics::with_local_recorder(&recorder, || {
+        Pool::builder()
+            .name("mock")
+            .maxsize(1)
+            .timeout(Some(Duration::from_millis(20)))
+            .build_unchecked(MockManager::new())
+    });
+
+    drop(pool.get().await.unwrap());
+    let con = pool.get().await.unwrap();
+    assert!(pool.get().await.unwrap_err().is_timeout());
+
+    let metrics = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| {
+            let key = key.key();
+            assert!(key
+                .labels()
+                .any(|l| l.key() == "pool" && l.value() == "mock"));
+            (key.name().to_owned(), value)
+        })
+        .collect::<std::collections::HashMap<_, _>>();
+    assert_eq!(metrics["flexc_checkouts_total"], DebugValue::Counter(2));
+    assert_eq!(metrics["flexc_timeouts_total"], DebugValue::Counter(1));
+    assert_eq!(metrics["flexc_inuse"], DebugValue::Gauge(1.0.into()));
+    let histogram = |name: &str| match &metrics[name] {
+        DebugValue::Histogram(values) => values.len(),
+        _ => unreachable!(),
+    };
+    assert_eq!(histogram("flexc_connect_seconds"), 1);
+    assert_eq!(histogram("flexc_check_seconds"), 2);
+    assert_eq!(histogram("flexc_wait_seconds"), 2);
+    drop(con);
+}
+
+#[atest]
+async fn test_slots() {
+    use flexc::SlotStatus;
+
+    let pool = Pool::builder()
+        .maxsize(2)
+        .build_unchecked(MockManager::new());
+    let con = pool.get().await.unwrap();
+    sleep(Duration::from_millis(5)).await;
+
+    let slots = pool.slots();
+    assert_eq!(slots.len(), 2);
+    let inuse = slots
+        .iter()
+        .find(|s| s.status == SlotStatus::Inuse)
+        .unwrap();
+    assert_eq!(inuse.uses, 1);
+    assert!(inuse.held.unwrap() >= Duration::from_millis(5));
+    assert!(inuse.age.unwrap() >= inuse.since_check.unwrap());
+    assert!(inuse.since_checkout.is_some());
+    let empty = slots
+        .iter()
+        .find(|s| s.status == SlotStatus::Empty)
+        .unwrap();
+    assert_eq!(empty.age, None);
+    assert_eq!(empty.held, None);
+
+    drop(con);
+    let slots = pool.slots();
+    assert!(slots
+        .iter()
+        .any(|s| s.status == SlotStatus::Idle && s.held.is_none()));
+}
+
+#[atest]
+async fn test_get_blocking() {
+    let manager = MockManager::new();
+    let pool = Arc::new(
+        Pool::builder()
+            .maxsize(2)
+            .timeout(Some(Duration::from_millis(100)))
+            .build_unchecked(manager.clone()),
+    );
+
+    let p = pool.clone();
+    let task = spawn_blocking(move || {
+        let con = p.get_blocking().unwrap();
+        let con2 = p.get_blocking_timeout(None).unwrap();
+        assert!(p.get_blocking().unwrap_err().is_timeout());
+        drop(con2);
+        // drop from another thread
+        std::thread::spawn(move || drop(con)).join().unwrap();
+        p.state()
+    });
+
+    let status = join(task).await;
+    assert_eq!(status.size, 2);
+    assert_eq!(status.idle, 2);
+    assert_eq!(manager.size(), 2);
+}
+
+#[cfg(all(
+    feature = "tokio-rt",
+    not(feature = "async-rt"),
+    not(feature = "smol-rt")
+))]
+#[test]
+fn test_get_blocking_runtime() {
+    // no runtime to drive it
+    let pool = Pool::builder().build_unchecked(MockManager::new());
+    assert!(pool.get_blocking().unwrap_err().is_runtime());
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let pool = rt.block_on(async {
+        Pool::builder()
+            .maxsize(1)
+            .build_unchecked(MockManager::new())
+    });
+    drop(pool.get_blocking().unwrap());
+    assert_eq!(pool.state().idle, 1);
+}
+
+#[cfg(feature = "tokio-rt")]
+#[test]
+fn test_runtime_dropped() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let pool = rt.block_on(async {
+        Pool::builder()
+            .maxsize(1)
+            .timeout(Some(Duration::from_secs(1)))
+            .build_unchecked(MockManager::new())
+    });
+    drop(rt);
+
+    // the timers are created in the runtime using the pool
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let con = pool.get().await.unwrap();
+        let res = pool.get_timeout(Some(Duration::from_millis(10))).await;
+        assert!(res.unwrap_err().is_timeout());
+        drop(con);
+    });
+}
+
+#[atest]
+async fn test_shards() {
+    const THREADS: usize = 8;
+    const MAX_SIZE: usize = 4;
+
+    let manager = MockManager::new();
+    let pool = Arc::new(
+        Pool::builder()
+            .maxsize(MAX_SIZE)
+            .shards(MAX_SIZE)
+            .check(None)
+            .build(manager.clone())
+            .await
+            .unwrap(),
+    );
+
+    let p = pool.clone();
+    let task = spawn_blocking(move || {
+        let threads = (0..THREADS)
+            .map(|_| {
+                let p = p.clone();
+                // steals from the other shards when the own one is empty
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let con = p.get_blocking_timeout(None).unwrap();
+                        std::thread::yield_now();
+                        drop(con);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        p.state()
+    });
+
+    let status = join(task).await;
+    assert_eq!(status.size, MAX_SIZE as u32);
+    assert_eq!(status.idle, MAX_SIZE as u32);
+    assert_eq!(manager.size(), MAX_SIZE);
+}
+
+#[atest]
+async fn test_return_before_permit() {
+    const THREADS: usize = 8;
+
+    let pool = Arc::new(
+        Pool::builder()
+            .maxsize(THREADS)
+            .shards(4)
+            .check(None)
+            .timeout(None)
+            .build(MockManager::new())
+            .await
+            .unwrap(),
+    );
+
+    // a connection per thread, the returned ones are always there for the permits
+    let retries = Arc::new(AtomicUsize::new(0));
+    let (p, r) = (pool.clone(), retries.clone());
+    let task = spawn_blocking(move || {
+        let threads = (0..THREADS)
+            .map(|_| {
+                let (p, retries) = (p.clone(), r.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        match futures::executor::block_on(p.try_get_timeout(None)) {
+                            Ok(Some(con)) => drop(con),
+                            Ok(None) => {
+                                retries.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(e) => panic!("{:?}", e),
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+    });
+
+    join(task).await;
+    assert_eq!(retries.load(Ordering::Relaxed), 0);
+    assert_eq!(pool.state().idle, THREADS as u32);
+}
+
+#[cfg(feature = "tokio-rt")]
+#[atest]
+async fn test_runtime_outside() {
+    let pool = Arc::new(
+        Pool::builder()
+            .maxsize(1)
+            .check(None)
+            .timeout(Some(Duration::from_millis(50)))
+            .runtime(flexc::TokioRuntime::current())
+            .build(MockManager::new())
+            .await
+            .unwrap(),
+    );
+
+    let p = pool.clone();
+    // timers of the pool run on the tokio runtime even polled by other executors
+    join(spawn_blocking(move || {
+        futures::executor::block_on(async {
+            let _con = p.get().await.unwrap();
+            assert!(p.get().await.unwrap_err().is_timeout());
+        })
+    }))
+    .await;
+    assert_eq!(pool.state().idle, 1);
+}
+
+#[atest]
+async fn test_close() {
+    let pool = Arc::new(
+        Pool::builder()
+            .maxsize(2)
+            .timeout(None)
+            .build_unchecked(MockManager::new()),
+    );
+    let con = pool.get().await.unwrap();
+    let con2 = pool.get().await.unwrap();
+
+    let waiters = (0..3)
+        .map(|_| {
+            let pool = pool.clone();
+            spawn(async move { pool.get().await.map(|_| ()) })
+        })
+        .collect::<Vec<_>>();
+    sleep(Duration::from_millis(10)).await;
+    assert_eq!(pool.state().inuse, 2);
+
+    pool.close();
+    assert!(pool.is_closed());
+
+    for waiter in waiters {
+        let res = timeout(Duration::from_secs(1), join(waiter))
+            .await
+            .expect("waiter woken");
+        assert!(res.unwrap_err().is_closed());
+    }
+    assert!(pool.try_get().await.unwrap_err().is_closed());
+
+    drop(con);
+    drop(con2);
+    assert_eq!(pool.state().size, 0);
+    assert_eq!(pool.state().empty, 2);
+
+    // the idle ones of every shard dropped
+    let manager = MockManager::new();
+    let pool = Pool::builder()
+        .maxsize(4)
+        .shards(2)
+        .build(manager.clone())
+        .await
+        .unwrap();
+    pool.close();
+    assert_eq!(pool.state().idle, 0);
+    assert_eq!(manager.size(), 0);
+}
+
+#[atest]
+async fn test_local_pool() {
+    use futures::future::join_all;
+
+    const TASKS: usize = 50;
+    let manager = LocalMockManager::default();
+    let pool = flexc::LocalPool::<LocalMockManager>::builder()
+        .maxsize(3)
+        .timeout(Some(Duration::from_millis(100)))
+        .build_local(manager.clone())
+        .await
+        .unwrap();
+    let status = pool.state();
+    assert_eq!(status.size, 3);
+    assert_eq!(status.idle, 3);
+
+    join_all((0..TASKS).map(|_| async {
+        let con = pool.get().await.unwrap();
+        con.set(con.get() + 1);
+        sleep(Duration::from_millis(1)).await;
+    }))
+    .await;
+    assert_eq!(manager.connects.get(), 3);
+    assert_eq!(pool.state().idle, 3);
+
+    let cons = [
+        pool.get().await.unwrap(),
+        pool.get().await.unwrap(),
+        pool.get().await.unwrap(),
+    ];
+    assert_eq!(cons.iter().map(|c| c.get()).sum::<usize>(), TASKS);
+    let status = pool.state();
+    assert_eq!(status.inuse, 3);
+    assert_eq!(status.idle, 0);
+    assert!(pool.try_get().await.unwrap().is_none());
+    assert!(pool.get().await.unwrap_err().is_timeout());
+
+    let [mut con, _, _] = cons;
+    con.set_reconnect(true);
+    drop(con);
+    let status = pool.state();
+    assert_eq!(status.inuse, 2);
+    assert_eq!(status.empty, 1);
+
+    pool.get().await.unwrap();
+    assert_eq!(manager.connects.get(), 4);
+}
+
+#[atest]
+async fn test_local_pool_recycle_held() {
+    let manager = LocalMockManager::default();
+    let pool = flexc::LocalPool::<LocalMockManager>::builder()
+        .maxsize(1)
+        .check(None)
+        .recycle_policy(|info: &flexc::ConnInfo| info.held < Duration::from_millis(20))
+        .build_local_unchecked(manager.clone());
+
+    // idle time is not held time
+    drop(pool.get().await.unwrap());
+    sleep(Duration::from_millis(30)).await;
+    drop(pool.get().await.unwrap());
+    assert_eq!(manager.connects.get(), 1);
+
+    // discard after held too long
+    let con = pool.get().await.unwrap();
+    sleep(Duration::from_millis(30)).await;
+    drop(con);
+    assert_eq!(pool.state().empty, 1);
+    drop(pool.get().await.unwrap());
+    assert_eq!(manager.connects.get(), 2);
+    assert_eq!(pool.state().idle, 1);
+}
+
+#[atest]
+async fn test_local_pool_recycle_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let panics = Arc::new(AtomicBool::new(false));
+    let panics2 = panics.clone();
+    let pool = flexc::LocalPool::<LocalMockManager>::builder()
+        .maxsize(1)
+        .check(None)
+        .timeout(Some(Duration::from_millis(100)))
+        .discard_on_panic(false)
+        .recycle_policy(move |_: &flexc::ConnInfo| {
+            assert!(!panics2.load(Ordering::Relaxed), "recycle policy");
+            true
+        })
+        .build_local_unchecked(LocalMockManager::default());
+
+    // on return, the connection is discarded and the slot given back before the panic resumes
+    let con = pool.get().await.unwrap();
+    panics.store(true, Ordering::Relaxed);
+    assert!(catch_unwind(AssertUnwindSafe(|| drop(con))).is_err());
+    let status = pool.state();
+    assert_eq!(status.empty, 1);
+    assert_eq!(status.inuse, 0);
+
+    // returned while unwinding, the policy panic doesn't abort
+    let con = pool.get().await.unwrap();
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let _con = con;
+        panic!("mid-operation");
+    }));
+    assert!(res.is_err());
+    assert_eq!(pool.state().empty, 1);
+
+    panics.store(false, Ordering::Relaxed);
+    let _con = pool.get().await.unwrap();
+    assert!(pool.get().await.unwrap_err().is_timeout());
+}
+
+#[atest]
+async fn test_local_pool_state() {
+    let pool = flexc::LocalPool::<LocalMockManager>::builder()
+        .maxsize(1)
+        .check(None)
+        .build_local_unchecked(LocalMockManager::default());
+
+    let mut get = Box::pin(pool.get());
+    assert!(futures::poll!(get.as_mut()).is_pending());
+    assert_eq!(pool.state().connecting, 1);
+    assert_eq!(pool.state().empty, 0);
+    let _con = get.await.unwrap();
+    assert_eq!(pool.state().connecting, 0);
+
+    let mut get = Box::pin(pool.get());
+    assert!(futures::poll!(get.as_mut()).is_pending());
+    sleep(Duration::from_millis(10)).await;
+    let status = pool.state();
+    assert_eq!(status.wait, 1);
+    assert!(status.oldest_wait.unwrap() >= Duration::from_millis(10));
+    drop(get);
+    assert_eq!(pool.state().oldest_wait, None);
+}
+
+struct BufferManager;
+
+impl flexc::ResourceManager for BufferManager {
+    type Resource = Vec<u8>;
+    type Error = ();
+
+    fn create(&self) -> Result<Self::Resource, Self::Error> {
+        Ok(Vec::with_capacity(16))
+    }
+    fn reset(&self, buf: &mut Self::Resource) {
+        buf.clear();
+    }
+    fn is_valid(&self, buf: &Self::Resource) -> bool {
+        buf.capacity() <= 1024
+    }
+}
+
+#[test]
+fn test_resource_pool_blocking() {
+    let pool = flexc::ResourcePool::<BufferManager>::builder()
+        .maxsize(2)
+        .timeout(Some(Duration::from_millis(20)))
+        .build_resources(BufferManager);
+
+    let mut buf = pool.get_blocking().unwrap();
+    buf.extend_from_slice(b"hello");
+    drop(buf);
+    let status = pool.state();
+    assert_eq!(status.size, 1);
+    assert_eq!(status.idle, 1);
+
+    let mut buf = pool.try_get().unwrap().unwrap();
+    assert!(buf.is_empty());
+    // too large to reuse
+    buf.reserve(4096);
+    let buf2 = pool.get_blocking().unwrap();
+    assert!(pool.try_get().unwrap().is_none());
+    assert!(pool.get_blocking().unwrap_err().is_timeout());
+
+    let pool = Arc::new(pool);
+    let p = pool.clone();
+    let waiter = std::thread::spawn(move || p.get_blocking_timeout(None).map(|b| b.capacity()));
+    std::thread::sleep(Duration::from_millis(10));
+    drop(buf);
+    assert_eq!(waiter.join().unwrap().unwrap(), 16);
+
+    assert_eq!(buf2.take().capacity(), 16);
+    let status = pool.state();
+    assert_eq!(status.empty, 1);
+    assert_eq!(status.idle, 1);
+}
+
+#[atest]
+async fn test_resource_pool() {
+    let pool = flexc::ResourcePool::<BufferManager>::builder()
+        .maxsize(1)
+        .timeout(Some(Duration::from_millis(20)))
+        .build_resources(BufferManager);
+
+    let buf = pool.get().await.unwrap();
+    assert!(pool.get().await.unwrap_err().is_timeout());
+    drop(buf);
+    assert_eq!(pool.get().await.unwrap().capacity(), 16);
+}
+
 #[atest]
 async fn test_move_drop() {
     let duration = Some(Duration::from_secs(1));
@@ -263,7 +1432,7 @@ async fn test_concurrent() {
     assert_eq!(status.size, MAX_SIZE);
     assert_eq!(status.idle, MAX_SIZE);
     assert_eq!(status.maxsize, MAX_SIZE);
-    assert_eq!(manager.size(), MAX_SIZE as _);
+    assert_eq!(manager.size(), MAX_SIZE as usize);
 
     // Spawn tasks
     let futures = (0..TASKS)
@@ -279,10 +1448,7 @@ async fn test_concurrent() {
 
     // Await tasks to finish
     for future in futures {
-        #[cfg(any(feature = "tokio-rt"))]
-        future.await.unwrap();
-        #[cfg(any(feature = "async-rt"))]
-        future.await;
+        join(future).await;
     }
 
     // Verify
@@ -291,7 +1457,7 @@ async fn test_concurrent() {
     assert_eq!(status.size, MAX_SIZE);
     assert_eq!(status.idle, MAX_SIZE);
     assert_eq!(status.maxsize, MAX_SIZE);
-    assert_eq!(manager.size(), MAX_SIZE as _);
+    assert_eq!(manager.size(), MAX_SIZE as usize);
 
     let values = [
         pool.get().await.unwrap(),
@@ -389,6 +1555,100 @@ async fn take(maxsize: usize, takes: usize) {
     pool.get().await.unwrap(); // error!
 }
 
+// connect always fails
+#[derive(Debug, Clone)]
+struct BrokenManager;
+
+#[async_trait]
+impl Manager for BrokenManager {
+    type Connection = ();
+    type Error = &'static str;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        sleep(Duration::from_millis(2)).await;
+        Err("refused")
+    }
+
+    async fn check(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[atest]
+async fn test_connect_error() {
+    let pool = flexc::Pool::<BrokenManager>::builder()
+        .maxsize(1)
+        .build_unchecked(BrokenManager);
+
+    let e = pool.get().await.unwrap_err();
+    assert!(e.is_inner() && e.is_connect() && !e.is_check());
+    match e {
+        flexc::Error::Connect { idx, elapsed, .. } => {
+            assert_eq!(idx, 0);
+            assert!(elapsed >= Duration::from_millis(2));
+        }
+        _ => unreachable!(),
+    }
+    let e = pool.get().await.unwrap_err();
+    assert!(e.to_string().starts_with("Connect conn-0 failed after"));
+    assert_eq!(e.into_inner(), Some("refused"));
+}
+
+// the connection is dirty if true, the reset fails for dirty ones
+#[derive(Debug, Clone, Default)]
+struct ResetManager {
+    resets: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl Manager for ResetManager {
+    type Connection = bool;
+    type Error = ();
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(false)
+    }
+
+    async fn check(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn reset(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        self.resets.fetch_add(1, Ordering::SeqCst);
+        if *conn {
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[atest]
+async fn test_release() {
+    let manager = ResetManager::default();
+    let pool = flexc::Pool::<ResetManager>::builder()
+        .maxsize(1)
+        .build_unchecked(manager.clone());
+
+    pool.get().await.unwrap().release().await.unwrap();
+    assert_eq!(manager.resets.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.state().idle, 1);
+
+    let mut con = pool.get().await.unwrap();
+    *con = true;
+    assert!(con.release().await.unwrap_err().is_inner());
+    assert_eq!(manager.resets.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.state().empty, 1);
+
+    // dropped without the reset
+    drop(pool.get().await.unwrap());
+    assert_eq!(manager.resets.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.state().idle, 1);
+
+    pool.get().await.unwrap().discard();
+    assert_eq!(pool.state().empty, 1);
+}
+
 #[atest]
 async fn test_bad_check() {
     const GETS: usize = 100;
@@ -412,7 +1672,8 @@ async fn test_bad_check() {
     }
 
     for _ in bad_start..bad_end {
-        assert!(pool.get().await.unwrap_err().is_inner());
+        let e = pool.get().await.unwrap_err();
+        assert!(e.is_inner() && e.is_check());
         errc += 1;
     }
 
@@ -424,7 +1685,7 @@ async fn test_bad_check() {
     assert_eq!(okc, GETS - bad_end + bad_start);
     assert_eq!(errc, bad_end - bad_start);
 
-    cons.drain(..).into_iter().map(|c| c.take()).count();
+    cons.drain(..).for_each(|c| drop(c.take()));
     for _ in 0..MAX_SIZE {
         cons.push(pool.get().await.unwrap());
     }